@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::warn;
+
+use crate::AppState;
+use crate::config::Config;
+use crate::routes::tokens_match;
+
+/// First lockout, doubled on each following one up to `MAX_LOCKOUT`
+const BASE_LOCKOUT: Duration = Duration::from_secs(60);
+const MAX_LOCKOUT: Duration = Duration::from_secs(3600);
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    since: Instant,
+    lockouts: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Failed token attempts per client, which lock the client out for a while once there are too
+/// many of them
+#[derive(Default)]
+pub struct AuthLimiter {
+    clients: Mutex<HashMap<IpAddr, Failures>>,
+}
+
+impl AuthLimiter {
+    /// Remaining lockout of `ip`, if any
+    fn locked(&self, ip: IpAddr) -> Option<Duration> {
+        let clients = self.clients.lock().unwrap();
+        let until = clients.get(&ip)?.locked_until?;
+        until.checked_duration_since(Instant::now())
+    }
+
+    fn failure(&self, ip: IpAddr, max_failures: u32, window: Duration) {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+
+        clients.retain(|_, f| {
+            f.since.elapsed() < window || f.locked_until.is_some_and(|until| until > now)
+        });
+
+        let failures = clients.entry(ip).or_insert(Failures {
+            count: 0,
+            since: now,
+            lockouts: 0,
+            locked_until: None,
+        });
+
+        if failures.since.elapsed() >= window {
+            failures.count = 0;
+            failures.since = now;
+        }
+
+        failures.count += 1;
+
+        if failures.count >= max_failures {
+            failures.lockouts += 1;
+            let lockout = BASE_LOCKOUT
+                .saturating_mul(2u32.saturating_pow(failures.lockouts - 1))
+                .min(MAX_LOCKOUT);
+            failures.locked_until = Some(now + lockout);
+            failures.count = 0;
+            failures.since = now;
+            warn!("Too many failed logins from {ip}, locked out for {lockout:?}");
+        }
+    }
+
+    fn success(&self, ip: IpAddr) {
+        self.clients.lock().unwrap().remove(&ip);
+    }
+}
+
+/// Address of the client. Behind one of the `trusted_proxies` it is the last address in
+/// `X-Forwarded-For` that is not a trusted proxy itself
+pub fn client_ip(config: &Config, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let trusted = &config.server.trusted_proxies;
+
+    if !trusted.contains(&peer) {
+        return peer;
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+        .rev()
+        .find(|ip| !trusted.contains(ip))
+        .unwrap_or(peer)
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Counts requests with an unknown bearer token and answers 429 to clients that sent too many
+pub async fn auth_guard(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(token) = bearer_token(request.headers()) else {
+        return next.run(request).await;
+    };
+
+    let config = state.config();
+    let ip = client_ip(&config, peer.ip(), request.headers());
+
+    if let Some(remaining) = state.auth_limiter.locked(ip) {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, remaining.as_secs().max(1).to_string())],
+            "Too many failed attempts",
+        )
+            .into_response();
+    }
+
+    let known = config
+        .admin_token
+        .as_deref()
+        .is_some_and(|admin_token| tokens_match(token, admin_token))
+        || config
+            .user
+            .iter()
+            .any(|user| tokens_match(token, &user.token));
+
+    match known {
+        true => state.auth_limiter.success(ip),
+        false => state.auth_limiter.failure(
+            ip,
+            config.auth_max_failures,
+            Duration::from_secs(config.auth_failure_window_secs),
+        ),
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(trusted_proxies: &str) -> Config {
+        toml::from_str(&format!("[server]\ntrusted_proxies = {trusted_proxies}")).unwrap()
+    }
+
+    fn forwarded_for(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn clients_behind_a_trusted_proxy_are_told_apart() {
+        let config = config(r#"["127.0.0.1"]"#);
+        let proxy = IpAddr::from([127, 0, 0, 1]);
+
+        assert_eq!(
+            client_ip(&config, proxy, &forwarded_for("10.0.0.7, 127.0.0.1")),
+            IpAddr::from([10, 0, 0, 7])
+        );
+        assert_eq!(client_ip(&config, proxy, &HeaderMap::new()), proxy);
+    }
+
+    #[test]
+    fn forwarded_addresses_from_other_peers_are_ignored() {
+        let config = config(r#"["127.0.0.1"]"#);
+        let peer = IpAddr::from([10, 0, 0, 9]);
+
+        assert_eq!(client_ip(&config, peer, &forwarded_for("10.0.0.7")), peer);
+    }
+
+    #[test]
+    fn lockouts_are_per_client() {
+        let limiter = AuthLimiter::default();
+        let window = Duration::from_secs(300);
+        let (client, other) = (IpAddr::from([10, 0, 0, 7]), IpAddr::from([10, 0, 0, 8]));
+
+        for _ in 0..3 {
+            assert!(limiter.locked(client).is_none());
+            limiter.failure(client, 3, window);
+        }
+
+        assert!(limiter.locked(client).is_some());
+        assert!(limiter.locked(other).is_none());
+    }
+}
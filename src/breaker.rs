@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::config::Config;
+use crate::helper::remote_host;
+
+/// A query that is not run because it failed `breaker_failures` times in a row
+#[derive(Debug)]
+pub struct Tripped {
+    failures: u32,
+    error: String,
+}
+
+impl std::fmt::Display for Tripped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "not queried after {} failures in a row, the last one: {}",
+            self.failures, self.error
+        )
+    }
+}
+
+impl std::error::Error for Tripped {}
+
+#[derive(Default)]
+struct Breaker {
+    failures: u32,
+    last_error: String,
+    /// Set once `failures` reached `breaker_failures`
+    open_until: Option<Instant>,
+}
+
+/// Consecutive failures of each query, by host and name (a unit name, or the batched query of all
+/// the units), so one that keeps failing (e.g. a permission issue) is only retried every
+/// `breaker_cooldown_secs`
+#[derive(Default)]
+pub struct Breakers {
+    breakers: Mutex<HashMap<String, Breaker>>,
+}
+
+impl Breakers {
+    /// Runs the `query` called `name` on the current host, unless it is still cooling down after
+    /// failing too often. Then `Tripped` is returned without running it. Once the cooldown is
+    /// over a single query is tried, and a new failure opens the breaker again
+    pub fn call<T>(
+        &self,
+        name: &str,
+        config: &Config,
+        query: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let key = match remote_host() {
+            Some(host) => format!("{host}/{name}"),
+            None => name.to_owned(),
+        };
+
+        if let Some(breaker) = self.breakers.lock().unwrap().get(&key)
+            && breaker
+                .open_until
+                .is_some_and(|until| Instant::now() < until)
+        {
+            return Err(Tripped {
+                failures: breaker.failures,
+                error: breaker.last_error.clone(),
+            }
+            .into());
+        }
+
+        // Not locked while querying, which can take up to the subprocess timeout
+        let result = query();
+
+        let mut breakers = self.breakers.lock().unwrap();
+
+        match &result {
+            Ok(_) => {
+                if breakers
+                    .remove(&key)
+                    .is_some_and(|b| b.open_until.is_some())
+                {
+                    info!("{key} can be queried again");
+                }
+            }
+            Err(e) => {
+                let breaker = breakers.entry(key.clone()).or_default();
+                breaker.failures += 1;
+                breaker.last_error = format!("{e:#}");
+
+                if breaker.failures >= config.breaker_failures {
+                    if breaker.open_until.is_none() {
+                        warn!(
+                            "{key} failed {} times in a row. Querying it every {}s only",
+                            breaker.failures, config.breaker_cooldown_secs
+                        );
+                    }
+                    breaker.open_until =
+                        Some(Instant::now() + Duration::from_secs(config.breaker_cooldown_secs));
+                }
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::on_host;
+    use anyhow::anyhow;
+    use std::cell::Cell;
+
+    fn config(cooldown_secs: u64) -> Config {
+        toml::from_str(&format!(
+            "breaker_failures = 2\nbreaker_cooldown_secs = {cooldown_secs}"
+        ))
+        .unwrap()
+    }
+
+    fn failing(runs: &Cell<u32>) -> Result<()> {
+        runs.set(runs.get() + 1);
+        Err(anyhow!("permission denied"))
+    }
+
+    #[test]
+    fn opens_after_failures_in_a_row() {
+        let breakers = Breakers::default();
+        let config = config(60);
+        let runs = Cell::new(0);
+
+        for _ in 0..2 {
+            let error = breakers
+                .call("show", &config, || failing(&runs))
+                .unwrap_err();
+            assert!(!error.is::<Tripped>());
+        }
+
+        // Open: the query is not run anymore
+        let error = breakers
+            .call("show", &config, || failing(&runs))
+            .unwrap_err();
+        assert!(error.is::<Tripped>());
+        assert!(error.to_string().contains("permission denied"), "{error}");
+        assert_eq!(runs.get(), 2);
+
+        // Other queries and the same query on another host are not affected
+        assert!(breakers.call("demo.service", &config, || Ok(())).is_ok());
+        assert!(on_host("web-1", || breakers.call("show", &config, || Ok(()))).is_ok());
+    }
+
+    #[test]
+    fn a_success_after_the_cooldown_closes_it() {
+        let breakers = Breakers::default();
+        let config = config(0);
+        let runs = Cell::new(0);
+
+        for _ in 0..3 {
+            let _ = breakers.call("show", &config, || failing(&runs));
+        }
+
+        // Tried again once the cooldown is over
+        assert_eq!(runs.get(), 3);
+
+        assert!(breakers.call("show", &config, || Ok(())).is_ok());
+        assert!(breakers.breakers.lock().unwrap().is_empty());
+    }
+}
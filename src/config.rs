@@ -0,0 +1,1456 @@
+use std::collections::HashSet;
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result, anyhow, bail};
+use chrono_tz::Tz;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table, Value};
+
+use log::warn;
+
+use crate::helper::{
+    DurationLabels, TimestampFormat, is_executable, on_host, preflight, systemctl_list_units,
+    systemctl_running_services,
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub service: Vec<ServiceConfig>,
+
+    /// Glob patterns expanded into `service` entries when the config is loaded
+    #[serde(default)]
+    pub pattern: Vec<PatternConfig>,
+
+    /// Language used for the duration labels (`1d 2h 3m 4s`)
+    #[serde(default)]
+    pub locale: Locale,
+
+    /// Timezone used to render absolute timestamps
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+
+    /// How the log view prints the time of each line: `short`, `iso` or `precise`
+    #[serde(default)]
+    pub log_timestamps: TimestampFormat,
+
+    /// Machine managed over SSH instead of the local one, or several shown side by side
+    pub host: Option<Hosts>,
+
+    /// Disables every action endpoint regardless of `allowed_actions`
+    #[serde(default)]
+    pub read_only: bool,
+
+    /// Maximum number of tasks running `systemctl`/`journalctl` processes at once
+    #[serde(default = "default_max_subprocesses")]
+    pub max_subprocesses: usize,
+
+    /// Seconds after which a `systemctl`/`journalctl` process is killed
+    #[serde(default = "default_subprocess_timeout_secs")]
+    pub subprocess_timeout_secs: u64,
+
+    /// Times a `systemctl` call failing with a transient error, e.g. while systemd reloads, is
+    /// run again
+    #[serde(default = "default_subprocess_retries")]
+    pub subprocess_retries: u32,
+
+    /// Seconds between two refreshes of the background poller
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+
+    /// How long the stop action waits for the service to be stopped before giving up on telling
+    /// how it ended
+    #[serde(default = "default_stop_wait_secs")]
+    pub stop_wait_secs: u64,
+
+    /// Failed queries in a row after which a service is only queried every
+    /// `breaker_cooldown_secs`, and shown as unavailable in between
+    #[serde(default = "default_breaker_failures")]
+    pub breaker_failures: u32,
+
+    #[serde(default = "default_breaker_cooldown_secs")]
+    pub breaker_cooldown_secs: u64,
+
+    /// Reads `systemctl is-system-running` on every poll, for the banner of `/services` and
+    /// `/summary`
+    #[serde(default = "default_check_system_state")]
+    pub check_system_state: bool,
+
+    /// Seconds between two reloads of the `/wall` status matrix
+    #[serde(default = "default_wall_refresh_secs")]
+    pub wall_refresh_secs: u64,
+
+    /// Days of polls the availability percentage of the cards is computed over
+    #[serde(default = "default_availability_window_days")]
+    pub availability_window_days: u64,
+
+    /// Polls in a row a service must be over its `memory_alert_bytes` or `cpu_alert_percent`
+    /// before it is reported, and back under before the report is cleared
+    #[serde(default = "default_alert_samples")]
+    pub alert_samples: u32,
+
+    /// Services the poller queries at the same time
+    #[serde(default = "default_poll_concurrency")]
+    pub poll_concurrency: usize,
+
+    /// Threads running the `systemctl`/`journalctl` calls of the handlers
+    #[serde(default = "default_blocking_threads")]
+    pub blocking_threads: usize,
+
+    /// `journalctl --follow` processes streaming logs at once
+    #[serde(default = "default_max_log_streams")]
+    pub max_log_streams: usize,
+
+    #[serde(default)]
+    pub server: ServerConfig,
+
+    /// Additional `systemctl show` properties listed on the detailed view, e.g. `CPUQuota`
+    #[serde(default)]
+    pub extra_properties: Vec<String>,
+
+    /// Shows the instances of a template unit (`worker@1.service`...) under a single header
+    #[serde(default)]
+    pub group_instances: bool,
+
+    /// Name of the dashboard when installed as an app
+    #[serde(default = "default_app_name")]
+    pub app_name: String,
+
+    /// Name of the machine shown in the footer. Defaults to the hostname
+    pub hostname: Option<String>,
+
+    /// PNG, SVG or ICO file served as the favicon and app icon. A gear is used when unset
+    pub favicon: Option<PathBuf>,
+
+    /// Bearer token required by the `/admin` endpoints. They are disabled when unset
+    pub admin_token: Option<String>,
+
+    /// Bearer tokens with a role. When there are users, requests without a known token can
+    /// only look
+    #[serde(default)]
+    pub user: Vec<UserConfig>,
+
+    /// Commands of their own that services can run from their detailed view, when listed in
+    /// their `custom_actions`
+    #[serde(default)]
+    pub custom_action: Vec<CustomAction>,
+
+    /// Failed token attempts from a client within `auth_failure_window_secs` before it is locked
+    /// out
+    #[serde(default = "default_auth_max_failures")]
+    pub auth_max_failures: u32,
+
+    #[serde(default = "default_auth_failure_window_secs")]
+    pub auth_failure_window_secs: u64,
+
+    /// Turns off the journal everywhere, whatever `show_logs` says
+    #[serde(default)]
+    pub disable_logs: bool,
+
+    /// Regular expressions whose matches are hidden from the status and the logs
+    #[serde(default)]
+    pub redact_patterns: Vec<String>,
+
+    /// `redact_patterns` compiled by `load_and_validate`
+    #[serde(skip)]
+    pub redact_regexes: Vec<Regex>,
+
+    /// Reloads the configuration when its file changes
+    #[serde(default)]
+    pub watch_config: bool,
+
+    /// Location of systemctl, resolved by `load_and_validate`
+    #[serde(skip)]
+    pub systemctl_path: PathBuf,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address to listen on. `DAEMON_MANAGER_ADDR` takes precedence
+    pub listen: Option<SocketAddr>,
+
+    /// Proxies whose `X-Forwarded-For` is used to find the client address
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+/// Shown instead of tokens when the configuration is returned by the API
+const REDACTED: &str = "********";
+
+impl Config {
+    /// The configuration as JSON with the tokens hidden
+    pub fn redacted(&self) -> Result<serde_json::Value> {
+        let mut value = serde_json::to_value(self).context("Unable to serialize the config")?;
+
+        if let Some(token) = value.get_mut("admin_token").filter(|t| !t.is_null()) {
+            *token = REDACTED.into();
+        }
+
+        if let Some(users) = value.get_mut("user").and_then(|u| u.as_array_mut()) {
+            for user in users {
+                user["token"] = REDACTED.into();
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// The configuration as TOML with the tokens hidden. Unset options are left out
+    pub fn redacted_toml(&self) -> Result<String> {
+        let mut value = self.redacted()?;
+        remove_nulls(&mut value);
+
+        toml::to_string(&value).context("Unable to serialize the config")
+    }
+}
+
+/// TOML has no null, an unset option is written as a missing key
+fn remove_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|_, v| !v.is_null());
+            map.values_mut().for_each(remove_nulls);
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(remove_nulls),
+        _ => {}
+    }
+}
+
+fn default_auth_max_failures() -> u32 {
+    5
+}
+
+fn default_auth_failure_window_secs() -> u64 {
+    300
+}
+
+fn default_app_name() -> String {
+    "Daemon Manager".to_owned()
+}
+
+fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
+fn default_max_subprocesses() -> usize {
+    8
+}
+
+fn default_subprocess_timeout_secs() -> u64 {
+    10
+}
+
+fn default_subprocess_retries() -> u32 {
+    2
+}
+
+fn default_poll_interval_secs() -> u64 {
+    30
+}
+
+fn default_stop_wait_secs() -> u64 {
+    300
+}
+
+fn default_breaker_failures() -> u32 {
+    3
+}
+
+fn default_breaker_cooldown_secs() -> u64 {
+    60
+}
+
+fn default_check_system_state() -> bool {
+    true
+}
+
+fn default_wall_refresh_secs() -> u64 {
+    20
+}
+
+fn default_availability_window_days() -> u64 {
+    7
+}
+
+fn default_alert_samples() -> u32 {
+    3
+}
+
+/// Leaves most of the `max_subprocesses` slots to the requests
+fn default_poll_concurrency() -> usize {
+    2
+}
+
+/// The tasks wait for one of the `max_subprocesses` slots before taking one of these threads, so
+/// there is no need for tokio's default of 512
+fn default_blocking_threads() -> usize {
+    32
+}
+
+/// Each open log panel holds a journalctl process for as long as it stays open
+fn default_max_log_streams() -> usize {
+    16
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Es,
+}
+
+impl Locale {
+    pub fn duration_labels(&self) -> DurationLabels {
+        match self {
+            Locale::En => DurationLabels {
+                days: "d",
+                hours: "h",
+                minutes: "m",
+                seconds: "s",
+            },
+            Locale::Es => DurationLabels {
+                days: "d",
+                hours: "h",
+                minutes: "min",
+                seconds: "s",
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceConfig {
+    pub service_name: String,
+    pub friendly_name: String,
+
+    #[serde(default)]
+    pub show_logs: bool,
+
+    #[serde(default)]
+    pub allowed_actions: Vec<Action>,
+
+    /// Emoji or short text shown next to the name on the card
+    pub icon: Option<String>,
+
+    /// CSS color of the card accent, e.g. `#89b4fa` or `teal`
+    pub color: Option<String>,
+
+    /// Shown under the name. Falls back to the unit's `Description`
+    pub description: Option<String>,
+
+    /// Runbooks, dashboards... shown as buttons on the card and the detailed view
+    #[serde(default)]
+    pub links: Vec<Link>,
+
+    /// Listed before the other services
+    #[serde(default)]
+    pub pinned: bool,
+
+    /// Free-form labels like `env:prod` or `team:data`, to filter the services by
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// `.socket` unit that activates the service, shown on its card. Actions can target it with
+    /// `?target=socket`
+    pub socket: Option<String>,
+
+    /// `SYSLOG_IDENTIFIER` the service logs under when it differs from the unit, e.g. `postfix`
+    /// for `postfix@-.service`. Its logs are then read by identifier rather than by unit
+    pub journal_identifier: Option<String>,
+
+    /// Shows the logs on the detailed view as a collapsed section, read only when it is opened
+    #[serde(default)]
+    pub logs_collapsed_by_default: bool,
+
+    /// Names of the `[[custom_action]]` entries the service can run
+    #[serde(default)]
+    pub custom_actions: Vec<String>,
+
+    /// Memory use the poller reports the service for when it stays above it
+    pub memory_alert_bytes: Option<u64>,
+
+    /// CPU use between two polls, in percent of one core, the poller reports the service for
+    /// when it stays above it
+    pub cpu_alert_percent: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Link {
+    pub label: String,
+    pub url: String,
+}
+
+/// Adds every unit matching `pattern` as a service sharing these display settings
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatternConfig {
+    pub pattern: String,
+
+    #[serde(default)]
+    pub show_logs: bool,
+
+    #[serde(default)]
+    pub allowed_actions: Vec<Action>,
+
+    pub icon: Option<String>,
+
+    pub color: Option<String>,
+
+    #[serde(default)]
+    pub pinned: bool,
+
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl PatternConfig {
+    fn expand(&self, service_name: String) -> ServiceConfig {
+        let friendly_name = service_name
+            .rsplit_once('.')
+            .map_or(service_name.as_str(), |(name, _)| name)
+            .to_owned();
+
+        ServiceConfig {
+            service_name,
+            friendly_name,
+            show_logs: self.show_logs,
+            allowed_actions: self.allowed_actions.clone(),
+            icon: self.icon.clone(),
+            color: self.color.clone(),
+            description: None,
+            links: Vec::new(),
+            pinned: self.pinned,
+            tags: self.tags.clone(),
+            socket: None,
+            journal_identifier: None,
+            logs_collapsed_by_default: false,
+            custom_actions: Vec::new(),
+            memory_alert_bytes: None,
+            cpu_alert_percent: None,
+        }
+    }
+}
+
+/// `host = "user@host"` for a single remote machine, or `[[host]]` entries for several
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Hosts {
+    Single(String),
+    Many(Vec<HostConfig>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostConfig {
+    /// Shown as the column header and used in `/host/{name}/services`
+    pub name: String,
+    /// `systemctl -H` target, e.g. `admin@web-1`
+    pub target: String,
+}
+
+impl Config {
+    /// The single remote machine, if `host` is set to one
+    pub fn remote_host(&self) -> Option<&str> {
+        match &self.host {
+            Some(Hosts::Single(host)) => Some(host),
+            _ => None,
+        }
+    }
+
+    /// The `[[host]]` entries, empty unless several machines are configured
+    pub fn hosts(&self) -> &[HostConfig] {
+        match &self.host {
+            Some(Hosts::Many(hosts)) => hosts,
+            _ => &[],
+        }
+    }
+
+    /// Machine the dashboard shows, for the footer: the configured `hostname`, the remote
+    /// `host` or the name of this machine
+    pub fn instance_hostname(&self) -> String {
+        self.hostname
+            .clone()
+            .or_else(|| self.remote_host().map(str::to_owned))
+            .or_else(sysinfo::System::host_name)
+            .unwrap_or_else(|| "unknown".to_owned())
+    }
+
+    pub fn find_host(&self, name: &str) -> Option<&HostConfig> {
+        self.hosts().iter().find(|host| host.name == name)
+    }
+}
+
+/// Placeholder of the unit name in the `command` of a custom action
+pub const UNIT_PLACEHOLDER: &str = "{unit}";
+
+/// A command run on a service from its detailed view, e.g. a wrapper that reloads certificates
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomAction {
+    /// Used in `custom_actions` and `/service/{service}/action/{name}`
+    pub name: String,
+    /// Text of the button. Defaults to the name
+    pub label: Option<String>,
+    /// Absolute path of the program and its arguments, run without a shell. `{unit}` is
+    /// replaced with the unit name, nothing else is
+    pub command: Vec<String>,
+}
+
+impl CustomAction {
+    pub fn label(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.name)
+    }
+
+    /// `command` with the unit name substituted
+    pub fn args_for(&self, unit: &str) -> Vec<String> {
+        self.command
+            .iter()
+            .map(|arg| arg.replace(UNIT_PLACEHOLDER, unit))
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    pub name: String,
+    pub token: String,
+    pub role: Role,
+}
+
+/// What a request may do. Each role can do everything the previous one can
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Sees the status and logs
+    Viewer,
+    /// Runs actions
+    Operator,
+    /// Reloads the config and toggles maintenance
+    Admin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Mask,
+    Unmask,
+    Kill,
+    Stop,
+    Restart,
+}
+
+impl Action {
+    /// The `systemctl` verb that performs this action
+    pub fn verb(&self) -> &'static str {
+        match self {
+            Action::Mask => "mask",
+            Action::Unmask => "unmask",
+            Action::Kill => "kill",
+            Action::Stop => "stop",
+            Action::Restart => "restart",
+        }
+    }
+}
+
+/// Where the configuration is read from
+#[derive(Debug, Clone)]
+pub enum ConfigSource {
+    File(PathBuf),
+    /// The TOML itself, in `DAEMON_MANAGER_CONFIG`
+    Env(String),
+    Stdin,
+}
+
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigSource::File(path) => write!(f, "{}", path.display()),
+            ConfigSource::Env(_) => write!(f, "DAEMON_MANAGER_CONFIG"),
+            ConfigSource::Stdin => write!(f, "stdin"),
+        }
+    }
+}
+
+/// Reads the configuration from `source`, parses it and checks that every service is usable.
+/// Stdin can only be read once, so it cannot be loaded again on reload
+pub fn load_and_validate(source: &ConfigSource) -> Result<Config> {
+    let config_str = match source {
+        ConfigSource::File(path) => std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read configuration file '{}'", path.display()))?,
+        ConfigSource::Env(config) => config.clone(),
+        ConfigSource::Stdin => {
+            let mut config = String::new();
+            std::io::stdin()
+                .read_to_string(&mut config)
+                .context("Could not read the configuration from stdin")?;
+            config
+        }
+    };
+
+    parse_and_validate(&config_str)
+}
+
+fn parse_and_validate(config_str: &str) -> Result<Config> {
+    let overrides: Vec<(String, String)> = std::env::vars()
+        .filter(|(key, _)| key.starts_with(ENV_PREFIX))
+        .collect();
+
+    // Going through a `toml::Value` loses the line numbers of errors, so only when needed
+    let mut config = match overrides.is_empty() {
+        true => toml::from_str(config_str).map_err(toml_error)?,
+        false => {
+            let value: toml::Value = toml::from_str(config_str).map_err(toml_error)?;
+            with_overrides(value, &overrides)?
+        }
+    };
+
+    config.systemctl_path = preflight(&config)?;
+
+    // The patterns are expanded on the remote host. With several hosts they are expected to run
+    // the same units, the first one is asked. The running configuration is left alone, the
+    // remote host only becomes the default one in `build_state`
+    let target = config
+        .hosts()
+        .first()
+        .map(|host| host.target.clone())
+        .or_else(|| config.remote_host().map(str::to_owned));
+
+    match target {
+        Some(target) => on_host(&target, || expand_patterns(&mut config))?,
+        None => expand_patterns(&mut config)?,
+    }
+
+    let mut host_names: Vec<&str> = config.hosts().iter().map(|h| h.name.as_str()).collect();
+    host_names.sort_unstable();
+
+    if matches!(&config.host, Some(Hosts::Many(hosts)) if hosts.is_empty()) {
+        bail!("host needs at least one entry");
+    }
+
+    if host_names.iter().any(|name| name.is_empty()) {
+        bail!("Host names cannot be empty");
+    }
+
+    if host_names.windows(2).any(|pair| pair[0] == pair[1]) {
+        bail!("Every host needs a different name");
+    }
+
+    let invalid: Vec<&str> = config
+        .service
+        .iter()
+        .filter(|s| s.service_name.rsplit_once('.').is_none())
+        .map(|s| s.service_name.as_str())
+        .collect();
+
+    if !invalid.is_empty() {
+        bail!("Invalid service name(s): {}", invalid.join(", "));
+    }
+
+    let invalid_colors: Vec<&str> = config
+        .service
+        .iter()
+        .filter_map(|s| s.color.as_deref())
+        .filter(|color| !is_valid_color(color))
+        .collect();
+
+    if !invalid_colors.is_empty() {
+        bail!("Invalid color(s): {}", invalid_colors.join(", "));
+    }
+
+    let invalid_links: Vec<&str> = config
+        .service
+        .iter()
+        .flat_map(|s| &s.links)
+        .filter(|link| !is_valid_url(&link.url))
+        .map(|link| link.url.as_str())
+        .collect();
+
+    if !invalid_links.is_empty() {
+        bail!(
+            "Invalid link(s), expected http(s) URLs: {}",
+            invalid_links.join(", ")
+        );
+    }
+
+    if config
+        .service
+        .iter()
+        .flat_map(|s| &s.tags)
+        .any(|tag| tag.is_empty() || tag.contains(char::is_whitespace))
+    {
+        bail!("Tags cannot be empty or contain spaces");
+    }
+
+    let invalid_sockets: Vec<&str> = config
+        .service
+        .iter()
+        .filter_map(|s| s.socket.as_deref())
+        .filter(|socket| !socket.ends_with(".socket"))
+        .collect();
+
+    if !invalid_sockets.is_empty() {
+        bail!(
+            "Invalid socket unit(s), expected names ending in .socket: {}",
+            invalid_sockets.join(", ")
+        );
+    }
+
+    if config
+        .service
+        .iter()
+        .filter_map(|s| s.journal_identifier.as_deref())
+        .any(|identifier| identifier.is_empty() || identifier.contains(char::is_whitespace))
+    {
+        bail!("journal_identifier cannot be empty or contain spaces");
+    }
+
+    validate_custom_actions(&config)?;
+
+    let invalid_properties: Vec<&str> = config
+        .extra_properties
+        .iter()
+        .filter(|p| !is_valid_property(p))
+        .map(|p| p.as_str())
+        .collect();
+
+    if !invalid_properties.is_empty() {
+        bail!(
+            "Invalid property name(s): {}",
+            invalid_properties.join(", ")
+        );
+    }
+
+    if let Some(favicon) = &config.favicon {
+        if favicon_type(favicon).is_none() {
+            bail!(
+                "Unsupported favicon '{}', expected a .png, .svg or .ico file",
+                favicon.display()
+            );
+        }
+        if !favicon.is_file() {
+            bail!("Favicon '{}' does not exist", favicon.display());
+        }
+    }
+
+    let mut tokens: Vec<&str> = config.user.iter().map(|u| u.token.as_str()).collect();
+    tokens.extend(config.admin_token.as_deref());
+    tokens.sort_unstable();
+
+    if tokens.iter().any(|token| token.is_empty()) {
+        bail!("Tokens cannot be empty");
+    }
+
+    if tokens.windows(2).any(|pair| pair[0] == pair[1]) {
+        bail!("Every user needs a different token");
+    }
+
+    config.redact_regexes = config
+        .redact_patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).with_context(|| format!("Invalid redact pattern '{pattern}'"))
+        })
+        .collect::<Result<_>>()?;
+
+    if config.auth_max_failures == 0 || config.auth_failure_window_secs == 0 {
+        bail!("auth_max_failures and auth_failure_window_secs must be greater than 0");
+    }
+
+    if config.max_subprocesses == 0 {
+        bail!("max_subprocesses must be greater than 0");
+    }
+
+    if config.subprocess_timeout_secs == 0 {
+        bail!("subprocess_timeout_secs must be greater than 0");
+    }
+
+    if config.subprocess_retries > 8 {
+        bail!("subprocess_retries must be at most 8");
+    }
+
+    if config.poll_interval_secs == 0 {
+        bail!("poll_interval_secs must be greater than 0");
+    }
+
+    if config.availability_window_days == 0 {
+        bail!("availability_window_days must be greater than 0");
+    }
+
+    // The polls are kept in memory for the whole window
+    if config.availability_window_days > 365 {
+        bail!("availability_window_days must be at most 365");
+    }
+
+    if config.alert_samples == 0 {
+        bail!("alert_samples must be greater than 0");
+    }
+
+    if config.breaker_failures == 0 {
+        bail!("breaker_failures must be greater than 0");
+    }
+
+    if config.wall_refresh_secs == 0 {
+        bail!("wall_refresh_secs must be greater than 0");
+    }
+
+    if let Some(service) = config
+        .service
+        .iter()
+        .find(|s| s.cpu_alert_percent.is_some_and(|percent| percent <= 0.0))
+    {
+        bail!(
+            "cpu_alert_percent of {} must be greater than 0",
+            service.service_name
+        );
+    }
+
+    if config.poll_concurrency == 0 {
+        bail!("poll_concurrency must be greater than 0");
+    }
+
+    if config.blocking_threads == 0 {
+        bail!("blocking_threads must be greater than 0");
+    }
+
+    if config.max_log_streams == 0 {
+        bail!("max_log_streams must be greater than 0");
+    }
+
+    Ok(config)
+}
+
+/// Services that differ between two configurations, by `service_name`
+#[derive(Debug, Default, Serialize)]
+pub struct ConfigDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+pub fn diff_services(old: &Config, new: &Config) -> ConfigDiff {
+    let mut diff = ConfigDiff::default();
+
+    for service in &new.service {
+        match old
+            .service
+            .iter()
+            .find(|s| s.service_name == service.service_name)
+        {
+            None => diff.added.push(service.service_name.clone()),
+            Some(previous) if previous != service => {
+                diff.changed.push(service.service_name.clone())
+            }
+            Some(_) => {}
+        }
+    }
+
+    diff.removed = old
+        .service
+        .iter()
+        .filter(|s| !new.service.iter().any(|n| n.service_name == s.service_name))
+        .map(|s| s.service_name.clone())
+        .collect();
+
+    diff
+}
+
+/// Held while the configuration file is rewritten so two edits do not overwrite each other
+static CONFIG_WRITE: Mutex<()> = Mutex::new(());
+
+/// Adds `service` as the last `[[service]]` of the configuration file, keeping the comments and
+/// formatting of the rest
+pub fn append_service(path: &Path, service: &ServiceConfig) -> Result<()> {
+    let entry = service_table(service)?;
+
+    edit_config_file(path, |document| {
+        document
+            .entry("service")
+            .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+            .as_array_of_tables_mut()
+            .context("service is not an array of tables")?
+            .push(entry);
+        Ok(())
+    })
+}
+
+/// Removes the `[[service]]` of `service_name` from the configuration file, keeping the comments
+/// and formatting of the rest
+pub fn remove_service(path: &Path, service_name: &str) -> Result<()> {
+    edit_config_file(path, |document| {
+        let services = document
+            .get_mut("service")
+            .and_then(Item::as_array_of_tables_mut)
+            .context("The configuration file has no services")?;
+
+        let before = services.len();
+        services.retain(|s| s.get("service_name").and_then(Item::as_str) != Some(service_name));
+
+        if services.len() == before {
+            bail!("{service_name} is not in the configuration file, it may come from a pattern");
+        }
+
+        Ok(())
+    })
+}
+
+/// `service` as a TOML table, without the fields left to their default
+fn service_table(service: &ServiceConfig) -> Result<Table> {
+    let document: DocumentMut = toml::to_string(service)
+        .context("Could not serialize the service")?
+        .parse()
+        .context("Could not parse the serialized service")?;
+
+    let mut table = Table::new();
+
+    for (key, item) in document.iter() {
+        let item = match item.clone() {
+            // `links`, written inline as the table is nested in the `service` array
+            Item::ArrayOfTables(tables) => Item::Value(Value::Array(tables.into_array())),
+            item => item,
+        };
+
+        let is_default = match item.as_value() {
+            Some(Value::Boolean(b)) => !*b.value(),
+            Some(Value::Array(a)) => a.is_empty(),
+            _ => false,
+        };
+
+        if !is_default {
+            table.insert(key, item);
+        }
+    }
+
+    table.decor_mut().set_prefix("\n");
+
+    Ok(table)
+}
+
+/// Applies `edit` to the configuration file at `path`. The result is only written if it is a
+/// valid configuration, and is renamed over the file so a crash never leaves half of it
+fn edit_config_file(path: &Path, edit: impl FnOnce(&mut DocumentMut) -> Result<()>) -> Result<()> {
+    let _guard = CONFIG_WRITE.lock().unwrap();
+
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read configuration file '{}'", path.display()))?;
+
+    let mut document: DocumentMut = text
+        .parse()
+        .with_context(|| format!("Could not parse configuration file '{}'", path.display()))?;
+
+    edit(&mut document)?;
+
+    let text = document.to_string();
+
+    parse_and_validate(&text).context("The edited configuration is not valid")?;
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    std::fs::write(&tmp, text).with_context(|| format!("Could not write '{}'", tmp.display()))?;
+
+    if let Ok(metadata) = std::fs::metadata(path) {
+        std::fs::set_permissions(&tmp, metadata.permissions())
+            .with_context(|| format!("Could not set the permissions of '{}'", tmp.display()))?;
+    }
+
+    std::fs::rename(&tmp, path)
+        .with_context(|| format!("Could not replace configuration file '{}'", path.display()))
+}
+
+fn toml_error(e: toml::de::Error) -> anyhow::Error {
+    let report = e.to_string();
+    match suggest_field(e.message()) {
+        Some(suggestion) => anyhow!("{}\nhelp: did you mean `{suggestion}`?", report.trim_end()),
+        None => anyhow!("{}", report.trim_end()),
+    }
+}
+
+/// Environment variables starting with this override config fields. Nested fields and array
+/// entries are separated by `__`, e.g. `DAEMON_MANAGER__SERVER__LISTEN` or
+/// `DAEMON_MANAGER__SERVICE__0__SHOW_LOGS`
+const ENV_PREFIX: &str = "DAEMON_MANAGER__";
+
+/// Applies the overrides to `config` and deserializes it. A field that expects a string gets the
+/// raw value even when it reads as another TOML type, like a password of `1234`
+fn with_overrides(config: toml::Value, overrides: &[(String, String)]) -> Result<Config> {
+    let mut as_string = vec![false; overrides.len()];
+
+    loop {
+        let mut value = config.clone();
+        for ((key, raw), &as_string) in overrides.iter().zip(&as_string) {
+            apply_override(&mut value, key, raw, as_string)?;
+        }
+
+        let error = match serde_path_to_error::deserialize(value) {
+            Ok(config) => return Ok(config),
+            Err(error) => error,
+        };
+
+        let failed: Vec<String> = error
+            .path()
+            .iter()
+            .map(|segment| match segment {
+                serde_path_to_error::Segment::Seq { index } => index.to_string(),
+                segment => segment.to_string(),
+            })
+            .collect();
+
+        // Only retried once per override, a field that refuses the string too is a real error
+        let retry = overrides
+            .iter()
+            .zip(&as_string)
+            .position(|((key, raw), &as_string)| {
+                !as_string && typed_value(raw).is_some() && override_path(key) == failed
+            });
+
+        match retry {
+            Some(index) => as_string[index] = true,
+            None => return Err(toml_error(error.into_inner())),
+        }
+    }
+}
+
+fn override_path(key: &str) -> Vec<String> {
+    key[ENV_PREFIX.len()..]
+        .split("__")
+        .map(str::to_ascii_lowercase)
+        .collect()
+}
+
+/// `true`, `8` or `["Restart"]` as their TOML type, `None` for anything that is only a string
+fn typed_value(raw: &str) -> Option<toml::Value> {
+    toml::from_str::<toml::Table>(&format!("value = {raw}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+}
+
+fn apply_override(config: &mut toml::Value, key: &str, raw: &str, as_string: bool) -> Result<()> {
+    let path = override_path(key);
+
+    if path.iter().any(String::is_empty) {
+        bail!("Invalid override {key}");
+    }
+
+    let value = match as_string {
+        true => None,
+        false => typed_value(raw),
+    }
+    .unwrap_or_else(|| toml::Value::String(raw.to_owned()));
+
+    set_value(config, &path, value).with_context(|| format!("Invalid override {key}"))
+}
+
+fn set_value(target: &mut toml::Value, path: &[String], value: toml::Value) -> Result<()> {
+    let Some((first, rest)) = path.split_first() else {
+        *target = value;
+        return Ok(());
+    };
+
+    match target {
+        toml::Value::Table(table) => {
+            let entry = table
+                .entry(first.clone())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            set_value(entry, rest, value)
+        }
+        toml::Value::Array(array) => {
+            let index: usize = first
+                .parse()
+                .with_context(|| format!("`{first}` is not an index"))?;
+            let entry = array
+                .get_mut(index)
+                .with_context(|| format!("There is no entry {index}"))?;
+            set_value(entry, rest, value)
+        }
+        _ => bail!("`{first}` is not a table"),
+    }
+}
+
+#[derive(Serialize)]
+struct GeneratedConfig {
+    service: Vec<ServiceConfig>,
+}
+
+/// A starter `services.toml` listing every running service
+pub fn generate_config() -> Result<String> {
+    let service = systemctl_running_services()?
+        .into_iter()
+        .map(|service_name| ServiceConfig {
+            friendly_name: service_name
+                .rsplit_once('.')
+                .map_or(service_name.as_str(), |(name, _)| name)
+                .to_owned(),
+            service_name,
+            show_logs: false,
+            allowed_actions: Vec::new(),
+            icon: None,
+            color: None,
+            description: None,
+            links: Vec::new(),
+            pinned: false,
+            tags: Vec::new(),
+            socket: None,
+            journal_identifier: None,
+            logs_collapsed_by_default: false,
+            custom_actions: Vec::new(),
+            memory_alert_bytes: None,
+            cpu_alert_percent: None,
+        })
+        .collect();
+
+    let body = toml::to_string(&GeneratedConfig { service })
+        .context("Could not serialize the generated config")?;
+
+    Ok(format!(
+        "# Generated by daemon-manager --generate-config from the running services.\n\
+         # Remove the services you do not want on the dashboard and rename them with\n\
+         # `friendly_name`. `show_logs = true` shows the journal on the detailed view and\n\
+         # `allowed_actions` lists the actions (mask, unmask, kill, stop, restart) that\n\
+         # can be run.\n\n{body}"
+    ))
+}
+
+/// Appends the units matching each `[[pattern]]` to `service`. Explicitly listed services win
+/// over discovered ones and a unit matched by several patterns is only added once
+fn expand_patterns(config: &mut Config) -> Result<()> {
+    for pattern in &config.pattern {
+        if pattern.pattern.starts_with('-') {
+            bail!("Invalid pattern: {}", pattern.pattern);
+        }
+
+        let units = systemctl_list_units(&pattern.pattern)
+            .with_context(|| format!("Could not expand pattern '{}'", pattern.pattern))?;
+
+        if units.is_empty() {
+            warn!("Pattern '{}' did not match any unit", pattern.pattern);
+        }
+
+        for unit in units {
+            if config.service.iter().any(|s| s.service_name == unit) {
+                continue;
+            }
+            config.service.push(pattern.expand(unit));
+        }
+    }
+
+    Ok(())
+}
+
+fn is_valid_url(url: &str) -> bool {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"));
+
+    rest.is_some_and(|rest| !rest.is_empty() && !rest.contains(char::is_whitespace))
+}
+
+/// Content type of a favicon, from its extension
+pub fn favicon_type(path: &std::path::Path) -> Option<&'static str> {
+    match path.extension()?.to_str()? {
+        "png" => Some("image/png"),
+        "svg" => Some("image/svg+xml"),
+        "ico" => Some("image/x-icon"),
+        _ => None,
+    }
+}
+
+/// Names and commands of the custom actions, and the services that can run them. Only the
+/// placeholder is substituted, so the unit names it stands for are checked too
+fn validate_custom_actions(config: &Config) -> Result<()> {
+    let mut names = HashSet::new();
+
+    for action in &config.custom_action {
+        let name = &action.name;
+
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        {
+            bail!("Invalid custom action name '{name}', expected lowercase letters, digits and -");
+        }
+
+        if !names.insert(name.as_str()) {
+            bail!("Custom action '{name}' is defined twice");
+        }
+
+        let Some((program, args)) = action.command.split_first() else {
+            bail!("Custom action '{name}' has no command");
+        };
+
+        if !program.starts_with('/') || program.contains(['{', '}']) {
+            bail!("The command of custom action '{name}' must start with an absolute path");
+        }
+
+        // Only checked on this machine, the program may only exist on the remote `host`
+        if config.remote_host().is_none() && !is_executable(Path::new(program)) {
+            bail!("{program}, the command of custom action '{name}', is not executable");
+        }
+
+        if let Some(arg) = args
+            .iter()
+            .find(|arg| arg.replace(UNIT_PLACEHOLDER, "").contains(['{', '}']))
+        {
+            bail!(
+                "Custom action '{name}': '{arg}' has a placeholder other than {UNIT_PLACEHOLDER}"
+            );
+        }
+    }
+
+    for service in &config.service {
+        if let Some(unknown) = service
+            .custom_actions
+            .iter()
+            .find(|name| !names.contains(name.as_str()))
+        {
+            bail!(
+                "{} lists the custom action '{unknown}', which is not defined",
+                service.service_name
+            );
+        }
+
+        if !service.custom_actions.is_empty() && !is_valid_unit_name(&service.service_name) {
+            bail!(
+                "{} cannot have custom actions, its name has characters units cannot have",
+                service.service_name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// The characters systemd allows in unit names
+fn is_valid_unit_name(name: &str) -> bool {
+    !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ":-_.@\\".contains(c))
+}
+
+/// systemd property names are CamelCase identifiers, which also keeps them from being read as
+/// extra `systemctl` arguments
+fn is_valid_property(property: &str) -> bool {
+    property.starts_with(|c: char| c.is_ascii_uppercase())
+        && property.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Accepts hex colors (`#fff`, `#89b4fa`) and named colors, which is all that is needed and keeps
+/// the value safe to put in a `style` attribute
+fn is_valid_color(color: &str) -> bool {
+    match color.strip_prefix('#') {
+        Some(hex) => {
+            matches!(hex.len(), 3 | 4 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit())
+        }
+        None => !color.is_empty() && color.chars().all(|c| c.is_ascii_alphabetic()),
+    }
+}
+
+/// Finds the closest expected field for an "unknown field" error from serde
+///
+/// The message looks like ``unknown field `serivce_name`, expected one of `service_name`, ...``
+/// so the first quoted word is the typo and the rest are the valid field names.
+fn suggest_field(message: &str) -> Option<&str> {
+    if !message.starts_with("unknown field") {
+        return None;
+    }
+
+    let mut quoted = message.split('`').skip(1).step_by(2);
+    let unknown = quoted.next()?;
+
+    quoted
+        .map(|candidate| (levenshtein(unknown, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= candidate.len().div_ceil(3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn misspelled_field_suggests_the_right_one() {
+        let config = r#"
+            [[service]]
+            service_name = "demo.service"
+            frendly_name = "Demo"
+        "#;
+
+        let error = toml::from_str::<Config>(config).unwrap_err();
+
+        assert!(error.message().contains("frendly_name"), "{error}");
+        assert_eq!(suggest_field(error.message()), Some("friendly_name"));
+    }
+
+    #[test]
+    fn unrelated_field_gets_no_suggestion() {
+        let config = r#"
+            [[service]]
+            service_name = "demo.service"
+            friendly_name = "Demo"
+            colour_scheme = "dark"
+        "#;
+
+        let error = toml::from_str::<Config>(config).unwrap_err();
+
+        assert_eq!(suggest_field(error.message()), None);
+    }
+
+    #[test]
+    fn overrides_reach_nested_fields_and_keep_their_type() {
+        let mut value: toml::Value = toml::from_str(
+            r#"
+            max_subprocesses = 8
+
+            [[service]]
+            service_name = "api.service"
+            friendly_name = "API"
+
+            [[service]]
+            service_name = "worker.service"
+            friendly_name = "Worker"
+        "#,
+        )
+        .unwrap();
+
+        let overrides = [
+            ("DAEMON_MANAGER__SERVER__LISTEN", "127.0.0.1:9000"),
+            ("DAEMON_MANAGER__MAX_SUBPROCESSES", "4"),
+            ("DAEMON_MANAGER__SERVICE__1__SHOW_LOGS", "true"),
+            (
+                "DAEMON_MANAGER__SERVICE__0__ALLOWED_ACTIONS",
+                r#"["mask", "kill"]"#,
+            ),
+            ("DAEMON_MANAGER__SERVICE__0__FRIENDLY_NAME", "Public API"),
+        ];
+
+        for (key, raw) in overrides {
+            apply_override(&mut value, key, raw, false).unwrap();
+        }
+
+        let config: Config = value.try_into().unwrap();
+
+        assert_eq!(
+            config.server.listen,
+            Some(SocketAddr::from(([127, 0, 0, 1], 9000)))
+        );
+        assert_eq!(config.max_subprocesses, 4);
+        assert!(!config.service[0].show_logs);
+        assert!(config.service[1].show_logs);
+        assert_eq!(
+            config.service[0].allowed_actions,
+            [Action::Mask, Action::Kill]
+        );
+        assert_eq!(config.service[0].friendly_name, "Public API");
+        assert_eq!(config.service[1].friendly_name, "Worker");
+    }
+
+    #[test]
+    fn overrides_of_string_fields_stay_strings() {
+        let value: toml::Value = toml::from_str(
+            r#"
+            [[service]]
+            service_name = "api.service"
+            friendly_name = "API"
+
+            [[user]]
+            name = "alice"
+            token = "secret"
+            role = "viewer"
+        "#,
+        )
+        .unwrap();
+
+        let overrides = [
+            ("DAEMON_MANAGER__USER__0__TOKEN", "1234"),
+            ("DAEMON_MANAGER__SERVICE__0__FRIENDLY_NAME", "true"),
+            ("DAEMON_MANAGER__SERVICE__0__SHOW_LOGS", "true"),
+        ]
+        .map(|(key, raw)| (key.to_owned(), raw.to_owned()));
+
+        let config = with_overrides(value, &overrides).unwrap();
+
+        assert_eq!(config.user[0].token, "1234");
+        assert_eq!(config.service[0].friendly_name, "true");
+        assert!(config.service[0].show_logs);
+    }
+
+    #[test]
+    fn overrides_outside_the_config_are_refused() {
+        let mut value: toml::Value = toml::from_str(
+            r#"
+            app_name = "Dashboard"
+
+            [[service]]
+            service_name = "api.service"
+            friendly_name = "API"
+        "#,
+        )
+        .unwrap();
+
+        for key in [
+            "DAEMON_MANAGER__SERVICE__1__SHOW_LOGS",
+            "DAEMON_MANAGER__SERVICE__FIRST__SHOW_LOGS",
+            "DAEMON_MANAGER__APP_NAME__SHORT",
+            "DAEMON_MANAGER__SERVICE____SHOW_LOGS",
+        ] {
+            assert!(
+                apply_override(&mut value, key, "true", false).is_err(),
+                "{key}"
+            );
+        }
+    }
+
+    #[test]
+    fn redacted_hides_the_tokens() {
+        let config: Config = toml::from_str(
+            r#"
+            admin_token = "admin-s3cret"
+
+            [[user]]
+            name = "alice"
+            token = "user-s3cret"
+            role = "viewer"
+        "#,
+        )
+        .unwrap();
+
+        for shown in [
+            config.redacted().unwrap().to_string(),
+            config.redacted_toml().unwrap(),
+        ] {
+            assert!(!shown.contains("admin-s3cret"), "{shown}");
+            assert!(!shown.contains("user-s3cret"), "{shown}");
+            assert!(shown.contains(REDACTED), "{shown}");
+            assert!(shown.contains("alice"), "{shown}");
+        }
+    }
+}
@@ -1,154 +1,2966 @@
-use anyhow::{Context, Result, anyhow};
-use std::process::Command;
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
 use std::str::FromStr;
-use std::time::{Duration, SystemTime};
-use systemctl::{AutoStartStatus, Unit};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+use systemctl::{AutoStartStatus, SystemCtl, Unit};
 
-use log::{debug, error};
+use log::{debug, error, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use sysinfo::System;
+use tokio::sync::Semaphore;
+use tokio::task::JoinError;
 
-use crate::{ServiceConfig, ServiceInfo};
+use crate::config::{Config, ServiceConfig};
+use crate::{ServiceDetail, ServiceInfo};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LimitSettings {
+    /// A process still running after this long is killed
+    timeout: Duration,
+    /// Times a command failing with a transient error is run again
+    retries: u32,
+}
+
+/// Processes are run without timeout nor retries until `set_subprocess_limit` is called
+static SUBPROCESS_SETTINGS: RwLock<Option<LimitSettings>> = RwLock::new(None);
+
+/// Slots of the tasks running `systemctl`/`journalctl`, with their number. Unlimited until
+/// `set_subprocess_limit` is called
+static SUBPROCESS_SLOTS: RwLock<Option<(usize, Arc<Semaphore>)>> = RwLock::new(None);
+
+/// Sets the maximum number of tasks running subprocesses at the same time, how long each process
+/// can run and how many times transient failures are retried. Called again on reload: tasks
+/// already running keep their slot and processes their timeout, the next ones get the new values
+pub fn set_subprocess_limit(permits: usize, timeout: Duration, retries: u32) {
+    *SUBPROCESS_SETTINGS.write().unwrap() = Some(LimitSettings { timeout, retries });
+
+    let mut slots = SUBPROCESS_SLOTS.write().unwrap();
+    if slots
+        .as_ref()
+        .is_none_or(|&(current, _)| current != permits)
+    {
+        *slots = Some((permits, Arc::new(Semaphore::new(permits))));
+    }
+}
+
+fn subprocess_settings() -> Option<LimitSettings> {
+    *SUBPROCESS_SETTINGS.read().unwrap()
+}
+
+/// `spawn_blocking` for work that runs `systemctl`/`journalctl`, once one of the
+/// `max_subprocesses` slots is free. The slot is taken first, so the tasks over the limit wait
+/// without holding a thread of the blocking pool
+pub async fn spawn_blocking_limited<F, T>(f: F) -> Result<T, JoinError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let slots = SUBPROCESS_SLOTS
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|(_, slots)| slots.clone());
+
+    let permit = match slots {
+        Some(slots) => slots.acquire_owned().await.ok(),
+        None => None,
+    };
+
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        f()
+    })
+    .await
+}
+
+static REMOTE_HOST: OnceLock<String> = OnceLock::new();
+
+/// Runs every `systemctl`/`journalctl` call on `host` (`user@host`) over SSH. Only the first call
+/// has an effect
+pub fn init_remote_host(host: &str) {
+    REMOTE_HOST.get_or_init(|| host.to_owned());
+}
+
+thread_local! {
+    /// Target of the `[[host]]` entry queried by this thread, see `on_host`
+    static CURRENT_HOST: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with its `systemctl`/`journalctl` calls going to `target`
+pub fn on_host<T>(target: &str, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT_HOST.replace(Some(target.to_owned()));
+    let result = f();
+    CURRENT_HOST.set(previous);
+    result
+}
+
+pub fn remote_host() -> Option<String> {
+    CURRENT_HOST
+        .with_borrow(|host| host.clone())
+        .or_else(|| REMOTE_HOST.get().cloned())
+}
+
+/// `command` rewritten to run on the remote host, if there is one. `systemctl` and
+/// `systemd-analyze` get `-H`; `journalctl` and custom actions have no such option and are run
+/// through `ssh`
+pub fn for_host(command: &Command) -> Option<Command> {
+    let host = remote_host()?;
+    let program = command.get_program();
+
+    if program == "systemctl" || program == "systemd-analyze" {
+        let mut remote = Command::new(program);
+        remote.arg("-H").arg(&host).args(command.get_args());
+        for (name, value) in command.get_envs() {
+            if let Some(value) = value {
+                remote.env(name, value);
+            }
+        }
+        return Some(remote);
+    }
+
+    let line = command
+        .get_envs()
+        .filter_map(|(name, value)| {
+            Some(format!(
+                "{}={}",
+                name.to_string_lossy(),
+                shell_quote(value?)
+            ))
+        })
+        .chain(std::iter::once(program.to_string_lossy().into_owned()))
+        .chain(command.get_args().map(shell_quote))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut remote = Command::new("ssh");
+    remote
+        .arg("-o")
+        .arg("BatchMode=yes")
+        .arg("--")
+        .arg(&host)
+        .arg(line);
+    Some(remote)
+}
+
+/// Quotes `arg` for the remote shell
+fn shell_quote(arg: &OsStr) -> String {
+    format!("'{}'", arg.to_string_lossy().replace('\'', r"'\''"))
+}
+
+/// Errors systemctl reports while systemd is busy, e.g. reloading, which go away on their own.
+/// Anything else, like a unit that does not exist, is not retried
+const TRANSIENT_ERRORS: [&str; 6] = [
+    "Connection reset by peer",
+    "Transport endpoint is not connected",
+    "Failed to connect to bus",
+    "Connection timed out",
+    "Resource temporarily unavailable",
+    "Activation of org.freedesktop.systemd1 timed out",
+];
+
+fn is_transient(output: &Output) -> bool {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    !output.status.success() && TRANSIENT_ERRORS.iter().any(|e| stderr.contains(e))
+}
+
+/// Processes started by `run` and the log streams, retries included, for `/metrics`
+pub static SUBPROCESSES_SPAWNED: AtomicU64 = AtomicU64::new(0);
+
+/// Runs `command` to completion. Fails with `TimedOut` and kills
+/// the process when it outlives the configured timeout. Transient failures are retried with an
+/// exponential backoff starting at 100ms
+fn run(command: &mut Command) -> std::io::Result<Output> {
+    let mut remote = for_host(command);
+    let command = remote.as_mut().unwrap_or(command);
+
+    let Some(limit) = subprocess_settings() else {
+        SUBPROCESSES_SPAWNED.fetch_add(1, Ordering::Relaxed);
+        return command.output();
+    };
+
+    let mut attempt = 0;
+
+    loop {
+        let output = run_once(limit.timeout, command)?;
+
+        if attempt >= limit.retries || !is_transient(&output) {
+            return Ok(output);
+        }
+
+        attempt += 1;
+
+        debug!(
+            "{} failed with a transient error, retry {attempt}/{}",
+            command.get_program().to_string_lossy(),
+            limit.retries
+        );
+
+        thread::sleep(Duration::from_millis(100) * 2u32.pow(attempt - 1));
+    }
+}
+
+fn run_once(timeout: Duration, command: &mut Command) -> std::io::Result<Output> {
+    SUBPROCESSES_SPAWNED.fetch_add(1, Ordering::Relaxed);
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Read while waiting, a full pipe would block the process until the timeout
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!(
+                    "{} timed out after {}s",
+                    command.get_program().to_string_lossy(),
+                    timeout.as_secs()
+                ),
+            ));
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+/// Like `run`, but stops `command` once it printed `max_lines` lines, for output that has no
+/// bound of its own. Not retried: the lines read so far are what the caller asked for
+fn run_head(command: &mut Command, max_lines: usize) -> std::io::Result<Output> {
+    let mut remote = for_host(command);
+    let command = remote.as_mut().unwrap_or(command);
+
+    let timeout = subprocess_settings().map(|limit| limit.timeout);
+    SUBPROCESSES_SPAWNED.fetch_add(1, Ordering::Relaxed);
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = head(child.stdout.take(), max_lines);
+    let stderr = drain(child.stderr.take());
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        // Enough lines were read, the rest would only be thrown away
+        if stdout.is_finished() {
+            let _ = child.kill();
+            break child.wait()?;
+        }
+
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!(
+                    "{} timed out after {}s",
+                    command.get_program().to_string_lossy(),
+                    timeout.unwrap_or_default().as_secs()
+                ),
+            ));
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Reads the first `max_lines` lines of `pipe`, then stops
+fn head<R: Read + Send + 'static>(pipe: Option<R>, max_lines: usize) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(pipe) = pipe {
+            let mut reader = BufReader::new(pipe);
+            for _ in 0..max_lines {
+                match reader.read_until(b'\n', &mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+            }
+        }
+        buffer
+    })
+}
+
+pub fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .find(|path| is_executable(path))
+    })
+}
+
+/// Makes sure `name` is an executable in `PATH` that answers `--version` and returns its path
+fn check_binary(name: &str) -> Result<PathBuf> {
+    let path = find_in_path(name)
+        .with_context(|| format!("{name} was not found in PATH or is not executable"))?;
+
+    let output = Command::new(&path)
+        .arg("--version")
+        .output()
+        .with_context(|| format!("Could not run '{} --version'", path.display()))?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "'{} --version' failed (status: {:?}): {}",
+            path.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(path)
+}
+
+/// Checks that the binaries the dashboard runs are available. journalctl is only needed when a
+/// service shows its logs. Returns the path of systemctl
+pub fn preflight(config: &Config) -> Result<PathBuf> {
+    let systemctl = check_binary("systemctl")?;
+    // Bounds the calls of the systemctl crate, see `build_systemctl`
+    check_binary("timeout")?;
+
+    let needs_journal = !config.disable_logs
+        && (config.service.iter().any(|s| s.show_logs)
+            || config.pattern.iter().any(|p| p.show_logs));
+
+    // Both run on the remote host through ssh
+    if config.host.is_some() {
+        check_binary("ssh")?;
+    } else if needs_journal {
+        check_binary("journalctl")?;
+    }
+
+    Ok(systemctl)
+}
+
+/// Client of the systemctl crate for `target`, or for the managed machine when there is none.
+/// The crate runs systemctl itself, so it is wrapped in `timeout` to be killed after
+/// `subprocess_timeout_secs` like the processes started by `run`
+pub fn build_systemctl(config: &Config, target: Option<&str>) -> SystemCtl {
+    let timeout = [
+        "--signal=KILL".to_owned(),
+        format!("{}s", config.subprocess_timeout_secs),
+        config.systemctl_path.to_string_lossy().into_owned(),
+    ];
+
+    let host = target
+        .or(config.remote_host())
+        .into_iter()
+        .flat_map(|host| ["-H".to_owned(), host.to_owned()]);
+
+    SystemCtl::builder()
+        .path("timeout".to_owned())
+        .additional_args(timeout.into_iter().chain(host).collect())
+        .build()
+}
+
+/// Checks that every configured service is a loaded unit. Masked units are accepted. With
+/// `[[host]]` entries every host is checked
+pub fn check_units(systemctl: &SystemCtl, config: &Config) -> Result<()> {
+    if config.hosts().is_empty() {
+        return check_host_units(systemctl, config);
+    }
+
+    for host in config.hosts() {
+        on_host(&host.target, || {
+            check_host_units(&build_systemctl(config, Some(&host.target)), config)
+        })
+        .with_context(|| format!("On host {}", host.name))?;
+    }
+
+    Ok(())
+}
+
+fn check_host_units(systemctl: &SystemCtl, config: &Config) -> Result<()> {
+    let mut invalid = Vec::new();
+
+    for service in &config.service {
+        let unit = match systemctl.create_unit(&service.service_name) {
+            Ok(unit) => unit,
+            Err(e) => {
+                error!("Failed to create unit for {}: {}", service.service_name, e);
+                invalid.push(service.service_name.as_str());
+                continue;
+            }
+        };
+
+        match unit.state {
+            systemctl::State::Loaded => {}
+            systemctl::State::Masked => match systemd_show_parse::<String>("LoadState", &unit.name)
+            {
+                Ok(load_state) if load_state == "masked" => warn!("Unit {} is masked", unit.name),
+                _ => {
+                    error!("Unit {} is not loaded (not found)", unit.name);
+                    invalid.push(service.service_name.as_str());
+                }
+            },
+        }
+    }
+
+    if !invalid.is_empty() {
+        bail!("Erroneous service(s): {}", invalid.join(", "));
+    }
+
+    Ok(())
+}
 
 pub fn systemd_show_parse<T>(variable: &str, unit: &str) -> Result<T>
 where
     T: FromStr,
     T::Err: std::error::Error + Send + Sync + 'static,
 {
-    Command::new("systemctl")
+    run(Command::new("systemctl")
         .arg("show")
         .arg(unit)
         .arg("--property")
         .arg(variable)
-        .arg("--value")
-        .output()
-        .context("Unable to get STDOUT")
-        .and_then(|output| {
-            if output.status.success() {
-                Ok(String::from_utf8(output.stdout)?
-                    .trim_end()
-                    .to_owned()
-                    .parse::<T>()
-                    .context("Unable to parse value")?)
-            } else {
-                Err(anyhow!(
-                    "systemctl failed (status: {:?}): {}",
-                    output.status,
-                    String::from_utf8_lossy(&output.stderr).trim()
-                ))
-            }
-        })
+        .arg("--value"))
+    .context("Unable to get STDOUT")
+    .and_then(|output| {
+        if output.status.success() {
+            Ok(String::from_utf8(output.stdout)?
+                .trim_end()
+                .to_owned()
+                .parse::<T>()
+                .context("Unable to parse value")?)
+        } else {
+            Err(anyhow!(
+                "systemctl failed (status: {:?}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    })
 }
 
-pub fn systemd_status_html(unit: &str) -> Result<String> {
-    let output = Command::new("systemctl")
-        .arg("status")
+/// Reads several properties with a single `systemctl show`. Returned in the requested order,
+/// with an empty value for the ones systemd does not know
+pub fn systemd_show_properties(unit: &str, properties: &[String]) -> Result<Vec<(String, String)>> {
+    let output = run(Command::new("systemctl")
+        .arg("show")
         .arg(unit)
-        .arg("--no-pager")
-        .arg("--lines")
-        .arg("0")
-        .arg("--full")
-        .arg("--legend=no")
-        .env("SYSTEMD_COLORS", "1")
-        .output()
-        .context("Unable to get STDOUT")?;
+        .arg("--property")
+        .arg(properties.join(",")))
+    .context("Unable to get STDOUT")?;
 
-    let raw =
-        String::from_utf8(output.stdout).context("Command output contains Non-UTF8 charachters")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "systemctl failed (status: {:?}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let raw = String::from_utf8(output.stdout)?;
+
+    let values: HashMap<&str, &str> = raw.lines().filter_map(|l| l.split_once('=')).collect();
 
-    ansi_to_html::convert(&raw).context("Unable to convert command output to HTML")
+    Ok(properties
+        .iter()
+        .map(|p| (p.clone(), values.get(p.as_str()).unwrap_or(&"").to_string()))
+        .collect())
 }
 
-pub fn journalctl_html(unit: &str) -> Result<String> {
-    let output = Command::new("journalctl")
-        .arg("-u")
+/// Reads a realtime timestamp property such as `ActiveEnterTimestamp`. `None` when it was never
+/// set, e.g. `ActiveEnterTimestamp` of a unit that has not been started
+pub fn systemd_show_timestamp(variable: &str, unit: &str) -> Result<Option<SystemTime>> {
+    let output = run(Command::new("systemctl")
+        .arg("show")
         .arg(unit)
-        .arg("--no-pager")
-        .arg("--lines")
-        .arg("100")
-        .output()
-        .context("Unable to get STDOUT")?;
+        .arg("--timestamp=unix")
+        .arg("--property")
+        .arg(variable)
+        .arg("--value"))
+    .context("Unable to get STDOUT")?;
 
-    let raw =
-        String::from_utf8(output.stdout).context("Command output contains Non-UTF8 charachters")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "systemctl failed (status: {:?}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let raw = String::from_utf8(output.stdout)?;
 
-    ansi_to_html::convert(&raw).context("Unable to convert command output to HTML")
+    parse_unix_timestamp(&raw)
 }
 
-pub fn monotonic_uptime(monotonic_us: u64, boot_time: SystemTime) -> String {
-    let event_time = boot_time + Duration::from_micros(monotonic_us);
-    let now = SystemTime::now();
-    let diff = now.duration_since(event_time).unwrap_or(Duration::ZERO);
-    format_duration(diff.as_secs())
+/// A `--timestamp=unix` value like `@1700000000`. Empty when the event did not happen
+fn parse_unix_timestamp(raw: &str) -> Result<Option<SystemTime>> {
+    match raw.trim().strip_prefix('@') {
+        Some(secs) => {
+            let secs = secs.parse::<u64>().context("Unable to parse value")?;
+            Ok(Some(std::time::UNIX_EPOCH + Duration::from_secs(secs)))
+        }
+        None => Ok(None),
+    }
 }
 
-fn format_duration(secs: u64) -> String {
-    let (days, hours, minutes, seconds) = (
-        secs / 86400,
-        (secs % 86400) / 3600,
-        (secs % 3600) / 60,
-        secs % 60,
-    );
+/// The `systemctl` call `systemctl_action` makes. `stop` and `restart` only queue their job with
+/// `--no-block`: a long `TimeoutStopSec` would outlast the subprocess timeout
+pub fn action_command(verb: &str, args: &[String], unit: &str) -> Command {
+    let mut command = Command::new("systemctl");
+    command.arg(verb);
+    if matches!(verb, "stop" | "restart") {
+        command.arg("--no-block");
+    }
+    command.args(args).arg(unit);
+    command
+}
 
-    let mut parts = vec![];
-    if days > 0 {
-        parts.push(format!("{days}d"));
+/// `command` as it would be typed in a shell, with the `-H` or `ssh` of the current host
+pub fn command_line(command: &Command) -> String {
+    let remote = for_host(command);
+    let command = remote.as_ref().unwrap_or(command);
+
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| {
+            let plain = arg.to_string_lossy();
+            match plain
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.@=:/+,".contains(c))
+            {
+                true => plain.into_owned(),
+                false => shell_quote(arg),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn systemctl_action(verb: &str, args: &[String], unit: &str) -> Result<()> {
+    let output = run(&mut action_command(verb, args, unit)).context("Unable to run systemctl")?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "systemctl {verb} failed (status: {:?}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
     }
-    if hours > 0 || !parts.is_empty() {
-        parts.push(format!("{hours}h"));
+}
+
+/// The `command` of a custom action, with the unit name already substituted
+pub fn custom_action_command(args: &[String]) -> Command {
+    let mut command = Command::new(&args[0]);
+    command.args(&args[1..]);
+    command
+}
+
+pub fn run_custom_action(name: &str, args: &[String]) -> Result<()> {
+    let output = run(&mut custom_action_command(args))
+        .with_context(|| format!("Unable to run custom action {name}"))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Custom action {name} failed (status: {:?}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
     }
-    if minutes > 0 || !parts.is_empty() {
-        parts.push(format!("{minutes}m"));
+}
+
+/// How a stop requested by `systemctl_stop` ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopOutcome {
+    /// Stopped within `TimeoutStopSec`
+    Stopped,
+    /// Still running at the end of `TimeoutStopSec`, so systemd killed it
+    ForceKilled,
+    /// Stopped, but systemd recorded another `Result`, e.g. `exit-code`
+    Other(String),
+    /// Not stopped yet when we stopped waiting
+    StillStopping,
+}
+
+impl std::fmt::Display for StopOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopOutcome::Stopped => write!(f, "stopped"),
+            StopOutcome::ForceKilled => write!(f, "force-killed after timeout"),
+            StopOutcome::Other(result) => write!(f, "stopped ({result})"),
+            StopOutcome::StillStopping => write!(f, "still stopping"),
+        }
     }
-    parts.push(format!("{seconds}s"));
+}
 
-    parts.join(" ")
+/// Time between two reads of `ActiveState` while waiting for a unit to stop
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Stops `unit` and tells whether it stopped on its own or had to be killed. The job is queued
+/// and `ActiveState` polled until it is done
+pub fn systemctl_stop(unit: &str, wait: Duration) -> Result<StopOutcome> {
+    systemctl_action("stop", &[], unit)?;
+
+    let deadline = Instant::now() + wait;
+
+    loop {
+        let state = systemd_show_parse::<String>("ActiveState", unit)?;
+
+        if matches!(state.as_str(), "inactive" | "failed") {
+            break;
+        }
+
+        if Instant::now() >= deadline {
+            return Ok(StopOutcome::StillStopping);
+        }
+
+        thread::sleep(STOP_POLL_INTERVAL);
+    }
+
+    Ok(
+        match systemd_show_parse::<String>("Result", unit)?.as_str() {
+            "success" => StopOutcome::Stopped,
+            "timeout" => StopOutcome::ForceKilled,
+            result => StopOutcome::Other(result.to_owned()),
+        },
+    )
 }
 
-pub fn get_boot_time() -> std::time::SystemTime {
-    let mut sys = System::new();
-    sys.refresh_all();
+/// Names of the loaded units matching `pattern`, e.g. `scraper-*.service`
+pub fn systemctl_list_units(pattern: &str) -> Result<Vec<String>> {
+    list_units(&["--all", pattern])
+}
 
-    let boot_time_secs = sysinfo::System::boot_time();
+/// Names of the services that are currently running
+pub fn systemctl_running_services() -> Result<Vec<String>> {
+    list_units(&["--type=service", "--state=running"])
+}
 
-    std::time::UNIX_EPOCH + std::time::Duration::from_secs(boot_time_secs)
+/// Coarse state (`active`, `inactive`, `failed`...) of each unit, in order, from a single
+/// `systemctl is-active` call. Much cheaper than `get_unit_info` when that is all that is needed
+pub fn systemctl_is_active(units: &[String]) -> Result<Vec<String>> {
+    systemctl_is("is-active", units)
 }
 
-pub fn get_unit_info(unit: &Unit, config: &[ServiceConfig]) -> Result<ServiceInfo> {
-    let main_pid = systemd_show_parse::<u64>("MainPID", &unit.name).ok();
+/// State of systemd as a whole: `running`, `degraded` when a unit failed, `maintenance`,
+/// `starting`...
+pub fn systemctl_is_system_running() -> Result<String> {
+    // Non-zero whenever the state is not `running`
+    let output =
+        run(Command::new("systemctl").arg("is-system-running")).context("Unable to get STDOUT")?;
 
-    let status_code = systemd_show_parse::<u8>("StatusErrno", &unit.name)
-        .map_err(|e| error!("StatusCode: {e}"))
-        .ok();
+    let state = String::from_utf8_lossy(&output.stdout).trim().to_owned();
 
-    let uptime: u64 = systemd_show_parse::<u64>("ExecMainStartTimestampMonotonic", &unit.name)?;
+    if state.is_empty() {
+        return Err(anyhow!(
+            "systemctl is-system-running returned no state: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
 
-    let boot_time = get_boot_time();
+    Ok(state)
+}
 
-    let pretty_uptime = monotonic_uptime(uptime, boot_time);
+/// `UnitFileState` of each unit (`enabled`, `disabled`, `static`...) from a single call
+pub fn systemctl_is_enabled(units: &[String]) -> Result<Vec<String>> {
+    systemctl_is("is-enabled", units)
+}
 
-    debug!("Unit Name: {}", unit.name);
+fn systemctl_is(verb: &str, units: &[String]) -> Result<Vec<String>> {
+    if units.is_empty() {
+        return Ok(Vec::new());
+    }
 
-    let unit_config = config
-        .iter()
-        .find(|a| {
-            a.service_name
-                .rsplit_once(".")
-                .map(|n| n.0 == unit.name)
-                .unwrap()
+    // The exit status is non-zero whenever a unit is not active/enabled, so only the output is
+    // checked
+    let output = run(Command::new("systemctl").arg(verb).arg("--").args(units))
+        .context("Unable to get STDOUT")?;
+
+    let raw =
+        String::from_utf8(output.stdout).context("Command output contains Non-UTF8 charachters")?;
+
+    let states: Vec<String> = raw.lines().map(|line| line.trim().to_owned()).collect();
+
+    if states.len() != units.len() {
+        return Err(anyhow!(
+            "systemctl {verb} returned {} states for {} units: {}",
+            states.len(),
+            units.len(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(states)
+}
+
+fn list_units(args: &[&str]) -> Result<Vec<String>> {
+    let output = run(Command::new("systemctl")
+        .arg("list-units")
+        .arg("--plain")
+        .arg("--full")
+        .arg("--no-legend")
+        .arg("--no-pager")
+        .args(args))
+    .context("Unable to get STDOUT")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "systemctl list-units failed (status: {:?}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let raw =
+        String::from_utf8(output.stdout).context("Command output contains Non-UTF8 charachters")?;
+
+    // Failed units are prefixed with a bullet
+    Ok(raw
+        .lines()
+        .filter_map(|line| {
+            line.split_whitespace()
+                .find(|field| *field != "●" && *field != "*")
+                .map(str::to_owned)
         })
-        .with_context(|| format!("Unable to get configuration of the service {}", unit.name))?;
+        .collect())
+}
 
-    Ok(ServiceInfo {
-        config: (*unit_config).clone(),
-        status: format!("{:?}", unit.state),
-        active: unit.active,
-        enabled: matches!(
-            unit.auto_start,
-            AutoStartStatus::Enabled | AutoStartStatus::EnabledRuntime
-        ),
-        running: main_pid != Some(0),
-        pid: main_pid,
-        status_code,
-        uptime: pretty_uptime,
+/// Replaces whatever matches one of `patterns` with `***`
+pub fn redact<'a>(text: &'a str, patterns: &[Regex]) -> Cow<'a, str> {
+    patterns.iter().fold(Cow::Borrowed(text), |text, pattern| {
+        match pattern.replace_all(&text, "***") {
+            Cow::Borrowed(_) => text,
+            Cow::Owned(replaced) => Cow::Owned(replaced),
+        }
     })
 }
+
+/// `systemctl status` exited with 4: systemd does not know the unit
+#[derive(Debug)]
+pub struct NoSuchUnit(pub String);
+
+impl std::fmt::Display for NoSuchUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unit {} not found", self.0)
+    }
+}
+
+impl std::error::Error for NoSuchUnit {}
+
+/// Output of `systemctl status`. Fails with `NoSuchUnit` when the unit does not exist; the
+/// exit codes of a unit that is not running (1 to 3) still give its status
+pub fn systemd_status_html(unit: &str, redact_patterns: &[Regex]) -> Result<String> {
+    let output = run(Command::new("systemctl")
+        .arg("status")
+        .arg(unit)
+        .arg("--no-pager")
+        .arg("--lines")
+        .arg("0")
+        .arg("--full")
+        .arg("--legend=no")
+        .env("SYSTEMD_COLORS", "1"))
+    .context("Unable to get STDOUT")?;
+
+    match output.status.code() {
+        // Active, or not running for one reason or another
+        Some(0..=3) => {}
+        Some(4) => return Err(NoSuchUnit(unit.to_owned()).into()),
+        _ => {
+            return Err(anyhow!(
+                "systemctl status {unit} failed (status: {:?}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+    }
+
+    let raw =
+        String::from_utf8(output.stdout).context("Command output contains Non-UTF8 charachters")?;
+
+    Ok(ansi_html(&redact(&raw, redact_patterns)))
+}
+
+/// The unit file with its drop-ins, as systemd merges them, with `redact_patterns` hidden
+pub fn get_unit_cat(unit: &str, redact_patterns: &[Regex]) -> Result<String> {
+    let output = run(Command::new("systemctl")
+        .arg("cat")
+        .arg("--no-pager")
+        .arg("--")
+        .arg(unit)
+        .env("SYSTEMD_COLORS", "1"))
+    .context("Unable to get STDOUT")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "systemctl cat {unit} failed (status: {:?}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let raw =
+        String::from_utf8(output.stdout).context("Command output contains Non-UTF8 charachters")?;
+
+    Ok(unit_file_html(&raw, redact_patterns))
+}
+
+/// `systemctl cat` output as HTML, with the secrets of its `Environment=` lines and
+/// `redact_patterns` hidden
+fn unit_file_html(raw: &str, redact_patterns: &[Regex]) -> String {
+    let masked = raw
+        .lines()
+        .map(|line| match line.strip_prefix("Environment=") {
+            Some(variables) => Cow::Owned(format!(
+                "Environment={}",
+                format_environment(&parse_environment(variables))
+            )),
+            None => Cow::Borrowed(line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    ansi_html(&redact(&masked, redact_patterns))
+}
+
+/// `text` with its colors as HTML. If they cannot be converted the text is still shown, escaped
+/// and without the escape sequences
+pub fn ansi_html(text: &str) -> String {
+    ansi_to_html::convert(text).unwrap_or_else(|e| {
+        error!("Unable to convert command output to HTML: {e}");
+        plain_html(text)
+    })
+}
+
+/// `text` escaped, without its escape sequences
+fn plain_html(text: &str) -> String {
+    let plain = Regex::new(r"\x1b(\[[0-?]*[ -/]*[@-~]|\][^\x07\x1b]*(\x07|\x1b\\)?|.)?")
+        .map(|escapes| escapes.replace_all(text, "").into_owned())
+        .unwrap_or_else(|_| text.replace('\x1b', ""));
+    htmlescape::encode_minimal(&plain)
+}
+
+fn systemd_analyze(args: &[&str]) -> Result<String> {
+    let output = run(Command::new("systemd-analyze")
+        .args(args)
+        .arg("--no-pager")
+        .env("SYSTEMD_COLORS", "1"))
+    .context("Unable to get STDOUT")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "systemd-analyze {} failed (status: {:?}): {}",
+            args.join(" "),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    String::from_utf8(output.stdout).context("Command output contains Non-UTF8 charachters")
+}
+
+/// The units `unit` waited for during boot, with the time each took
+pub fn systemd_critical_chain_html(unit: &str) -> Result<String> {
+    let raw = systemd_analyze(&["critical-chain", unit])?;
+
+    Ok(ansi_html(&raw))
+}
+
+/// The line of `systemd-analyze blame` with the time `unit` took to start
+pub fn systemd_blame_html(unit: &str) -> Result<String> {
+    let raw = systemd_analyze(&["blame"])?;
+
+    let line = raw
+        .lines()
+        .find(|line| line.split_whitespace().last() == Some(unit))
+        .unwrap_or_default();
+
+    Ok(ansi_html(line))
+}
+
+/// A unit of `systemctl list-dependencies` with the units it pulls in
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DependencyNode {
+    pub unit: String,
+    /// `ActiveState` of the unit, e.g. `active` or `failed`
+    pub state: String,
+    pub children: Vec<DependencyNode>,
+}
+
+/// The dependency tree of `unit`. systemd expands targets recursively and stops at the others
+pub fn systemctl_list_dependencies(unit: &str) -> Result<DependencyNode> {
+    let output = run(Command::new("systemctl")
+        .arg("list-dependencies")
+        .arg("--no-pager")
+        .arg("--full")
+        .arg("--")
+        .arg(unit))
+    .context("Unable to get STDOUT")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "systemctl list-dependencies {unit} failed (status: {:?}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let raw =
+        String::from_utf8(output.stdout).context("Command output contains Non-UTF8 charachters")?;
+
+    let mut root = parse_dependency_tree(&raw)
+        .ok_or_else(|| anyhow!("systemctl list-dependencies {unit} printed nothing"))?;
+
+    // The bullets only tell the states apart by their color, and not at all in a C locale
+    let mut units = Vec::new();
+    collect_units(&root, &mut units);
+    units.sort_unstable();
+    units.dedup();
+
+    let states = systemctl_active_states(&units)?;
+    set_states(&mut root, &states);
+
+    Ok(root)
+}
+
+/// Pairs of characters indenting a level of `systemctl list-dependencies`, in a UTF-8 and in a C
+/// locale
+const TREE_INDENTS: [&str; 3] = ["│ ", "  ", "| "];
+
+/// Pairs of characters before the name of a unit of `systemctl list-dependencies`
+const TREE_BRANCHES: [&str; 4] = ["├─", "└─", "|-", "`-"];
+
+/// Parses the output of `systemctl list-dependencies --full` without the states. The first line
+/// is the unit itself. Each of the others is a state bullet (`●`, `○` or `×`, `*` or `x` in a C
+/// locale) or a space, a space, two characters per level and the name of a dependency
+fn parse_dependency_tree(raw: &str) -> Option<DependencyNode> {
+    let mut lines = raw.lines().filter(|line| !line.trim().is_empty());
+
+    let root = lines.next()?.split_whitespace().last()?;
+
+    let mut stack: Vec<(usize, DependencyNode)> = vec![(0, dependency_node(root))];
+    let mut tree = None;
+
+    for line in lines {
+        let Some((depth, unit)) = parse_dependency_line(line) else {
+            warn!("Unexpected line in systemctl list-dependencies: {line}");
+            continue;
+        };
+
+        while stack.last().is_some_and(|(d, _)| *d >= depth) {
+            tree = attach_to_parent(&mut stack).or(tree);
+        }
+
+        stack.push((depth, dependency_node(unit)));
+    }
+
+    while !stack.is_empty() {
+        tree = attach_to_parent(&mut stack).or(tree);
+    }
+
+    tree
+}
+
+/// Depth and unit of a line of `systemctl list-dependencies` below the first one
+fn parse_dependency_line(line: &str) -> Option<(usize, &str)> {
+    let mut chars = line.chars();
+    chars.next()?;
+    let mut rest = chars.as_str().strip_prefix(' ')?;
+    let mut depth = 1;
+
+    while let Some(after) = TREE_INDENTS
+        .iter()
+        .find_map(|indent| rest.strip_prefix(indent))
+    {
+        rest = after;
+        depth += 1;
+    }
+
+    // Matched as a whole, as a unit can start with `-` like the branch in a C locale: `|--.mount`
+    let unit = TREE_BRANCHES
+        .iter()
+        .find_map(|branch| rest.strip_prefix(branch))?;
+
+    Some((depth, unit.trim_end()))
+}
+
+fn dependency_node(unit: &str) -> DependencyNode {
+    DependencyNode {
+        unit: unit.to_owned(),
+        state: String::new(),
+        children: Vec::new(),
+    }
+}
+
+fn collect_units(node: &DependencyNode, units: &mut Vec<String>) {
+    units.push(node.unit.clone());
+    for child in &node.children {
+        collect_units(child, units);
+    }
+}
+
+fn set_states(node: &mut DependencyNode, states: &HashMap<String, String>) {
+    node.state = states.get(&node.unit).cloned().unwrap_or_default();
+    for child in &mut node.children {
+        set_states(child, states);
+    }
+}
+
+/// `ActiveState` of each of `units` from a single `systemctl show` call
+fn systemctl_active_states(units: &[String]) -> Result<HashMap<String, String>> {
+    let output = run(Command::new("systemctl")
+        .arg("show")
+        .arg("--property=ActiveState")
+        .arg("--")
+        .args(units))
+    .context("Unable to get STDOUT")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "systemctl show failed (status: {:?}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let blocks = parse_show_blocks(&String::from_utf8_lossy(&output.stdout));
+
+    // Matched by position like `systemctl_show_units`
+    Ok(units
+        .iter()
+        .zip(&blocks)
+        .map(|(unit, block)| {
+            let state = block.value("ActiveState").unwrap_or_default().to_owned();
+            (unit.clone(), state)
+        })
+        .collect())
+}
+
+/// Moves the last node of `stack` into the children of the one before it. Returns it when it
+/// was the root
+fn attach_to_parent(stack: &mut Vec<(usize, DependencyNode)>) -> Option<DependencyNode> {
+    let (_, node) = stack.pop()?;
+
+    match stack.last_mut() {
+        Some((_, parent)) => {
+            parent.children.push(node);
+            None
+        }
+        None => Some(node),
+    }
+}
+
+/// Oldest boot that can be selected on the log view, relative to the current one
+pub const MAX_BOOT_OFFSET: i32 = -100;
+
+/// Syslog priorities grouped the way the log view colors them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn from_priority(priority: u8) -> Self {
+        match priority {
+            0..=3 => LogLevel::Error,
+            4 => LogLevel::Warning,
+            5 | 6 => LogLevel::Info,
+            _ => LogLevel::Debug,
+        }
+    }
+
+    /// Least important priority included when filtering by this level
+    pub fn max_priority(&self) -> u8 {
+        match self {
+            LogLevel::Error => 3,
+            LogLevel::Warning => 4,
+            LogLevel::Info => 6,
+            LogLevel::Debug => 7,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warning => "warning",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+}
+
+/// Which journal entries to fetch
+#[derive(Debug, Clone)]
+pub struct JournalFilter {
+    pub lines: u32,
+    /// journalctl boot offset: 0 is the current boot, -1 the previous one... All boots when unset
+    pub boot: Option<i32>,
+    /// Only entries of this level and more important ones
+    pub level: Option<LogLevel>,
+    /// Only entries written after this time, e.g. since the last start of the service
+    pub since: Option<SystemTime>,
+    /// Only entries whose message matches this regular expression, e.g. `timeout|refused`
+    pub grep: Option<String>,
+    /// Only entries after this `__CURSOR`, the oldest `lines` of them rather than the newest
+    pub after_cursor: Option<String>,
+    /// How `journalctl_html` prints the time of each line
+    pub timestamps: TimestampFormat,
+    /// The `journal_identifier` of the service, read instead of the unit
+    pub identifier: Option<String>,
+}
+
+/// journalctl arguments selecting the entries of each unit, or of its `journal_identifier` when
+/// it has one
+pub fn journal_matches(units: &[(&str, Option<&str>)]) -> Vec<String> {
+    if units.iter().all(|(_, identifier)| identifier.is_none()) {
+        return units
+            .iter()
+            .flat_map(|(unit, _)| ["-u".to_owned(), unit.to_string()])
+            .collect();
+    }
+
+    // `-u` and `-t` would both have to match, while field matches separated by `+` are
+    // alternatives
+    let mut matches = Vec::new();
+
+    for (unit, identifier) in units {
+        if !matches.is_empty() {
+            matches.push("+".to_owned());
+        }
+
+        matches.push(match identifier {
+            Some(identifier) => format!("SYSLOG_IDENTIFIER={identifier}"),
+            None => format!("_SYSTEMD_UNIT={unit}"),
+        });
+    }
+
+    matches
+}
+
+/// How the log view prints the time of each line, in the configured `timezone`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampFormat {
+    /// `2024-05-01 12:00:00 CEST`
+    #[default]
+    Short,
+    /// `2024-05-01T12:00:00+02:00`, to compare with the logs of other systems
+    Iso,
+    /// `2024-05-01 12:00:00.123456 CEST`
+    Precise,
+}
+
+impl TimestampFormat {
+    fn format(&self, realtime_us: u64, timezone: &Tz) -> String {
+        let time =
+            DateTime::<Utc>::from(std::time::UNIX_EPOCH + Duration::from_micros(realtime_us))
+                .with_timezone(timezone);
+
+        match self {
+            TimestampFormat::Short => time.format("%Y-%m-%d %H:%M:%S %Z").to_string(),
+            TimestampFormat::Iso => time.format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+            TimestampFormat::Precise => time.format("%Y-%m-%d %H:%M:%S%.6f %Z").to_string(),
+        }
+    }
+}
+
+/// journalctl rejected the `grep` of a `JournalFilter`
+#[derive(Debug)]
+pub struct BadPattern(pub String);
+
+impl std::fmt::Display for BadPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for BadPattern {}
+
+/// journalctl could not seek to the `after_cursor` of a `JournalFilter`
+#[derive(Debug)]
+pub struct BadCursor(pub String);
+
+impl std::fmt::Display for BadCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for BadCursor {}
+
+/// Renders the journal of `unit` as HTML, one `log-<level>` span per entry
+pub fn journalctl_html(
+    unit: &str,
+    filter: &JournalFilter,
+    timezone: &Tz,
+    redact_patterns: &[Regex],
+) -> Result<String> {
+    journalctl_json(unit, filter, timezone, redact_patterns)?
+        .iter()
+        .map(|entry| log_entry_html(entry, filter.timestamps, timezone))
+        .collect()
+}
+
+/// One line of the HTML log view
+pub fn log_entry_html(
+    entry: &LogEntry,
+    timestamps: TimestampFormat,
+    timezone: &Tz,
+) -> Result<String> {
+    let message = ansi_html(&entry.message);
+
+    let source = match (&entry.identifier, entry.pid) {
+        (Some(identifier), Some(pid)) => format!("{identifier}[{pid}]"),
+        (Some(identifier), None) => identifier.clone(),
+        (None, _) => String::new(),
+    };
+
+    Ok(format!(
+        "<span class=\"log-{}\">{} {}: {message}</span>\n",
+        entry.level.as_str(),
+        timestamps.format(entry.realtime_us, timezone),
+        htmlescape::encode_minimal(&source),
+    ))
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogEntry {
+    /// Microseconds since the Unix epoch
+    pub realtime_us: u64,
+    pub timestamp: String,
+    /// Syslog priority, 0 (emerg) to 7 (debug)
+    pub priority: u8,
+    pub level: LogLevel,
+    pub message: String,
+    pub identifier: Option<String>,
+    pub pid: Option<u32>,
+    pub cursor: String,
+    /// Unit that wrote the entry, or that systemd wrote it about
+    pub unit: Option<String>,
+}
+
+impl LogEntry {
+    /// Parses one line of `journalctl --output=json`, hiding what matches `redact_patterns`
+    pub fn from_json(line: &str, timezone: &Tz, redact_patterns: &[Regex]) -> Result<Self> {
+        let entry: serde_json::Value =
+            serde_json::from_str(line).context("Unable to parse journal entry")?;
+
+        let field = |name: &str| entry.get(name).and_then(|v| v.as_str());
+
+        let realtime_us = field("__REALTIME_TIMESTAMP")
+            .and_then(|v| v.parse::<u64>().ok())
+            .context("Journal entry has no timestamp")?;
+
+        // Non UTF-8 messages are exported as an array of bytes
+        let message = match entry.get("MESSAGE") {
+            Some(serde_json::Value::String(message)) => message.clone(),
+            Some(serde_json::Value::Array(bytes)) => String::from_utf8_lossy(
+                &bytes
+                    .iter()
+                    .filter_map(|b| b.as_u64().map(|b| b as u8))
+                    .collect::<Vec<u8>>(),
+            )
+            .into_owned(),
+            _ => String::new(),
+        };
+
+        let message = redact(&message, redact_patterns).into_owned();
+
+        let priority = field("PRIORITY").and_then(|v| v.parse().ok()).unwrap_or(6);
+
+        Ok(LogEntry {
+            realtime_us,
+            timestamp: format_timestamp(
+                std::time::UNIX_EPOCH + Duration::from_micros(realtime_us),
+                timezone,
+            ),
+            priority,
+            level: LogLevel::from_priority(priority),
+            message,
+            identifier: field("SYSLOG_IDENTIFIER").map(str::to_owned),
+            pid: field("_PID").and_then(|v| v.parse().ok()),
+            cursor: field("__CURSOR").unwrap_or_default().to_owned(),
+            unit: field("_SYSTEMD_UNIT")
+                .or_else(|| field("UNIT"))
+                .map(str::to_owned),
+        })
+    }
+}
+
+/// The last journal entries of `unit` matching `filter`, oldest first
+pub fn journalctl_json(
+    unit: &str,
+    filter: &JournalFilter,
+    timezone: &Tz,
+    redact_patterns: &[Regex],
+) -> Result<Vec<LogEntry>> {
+    let mut command = Command::new("journalctl");
+
+    command
+        .args(journal_matches(&[(unit, filter.identifier.as_deref())]))
+        .arg("--output=json")
+        .arg("--no-pager");
+
+    // `--lines` keeps the newest entries, which would leave a gap after the cursor when more
+    // were written. journalctl is stopped after the oldest ones instead, see below
+    match &filter.after_cursor {
+        Some(cursor) => command.arg(format!("--after-cursor={cursor}")),
+        None => command.arg("--lines").arg(filter.lines.to_string()),
+    };
+
+    // The newest entries are those of the current boot, and `--boot=0` would make journalctl look
+    // the boot up in the whole journal first
+    if let Some(boot) = filter.boot.filter(|&boot| boot != 0) {
+        command.arg(format!("--boot={boot}"));
+    }
+
+    if let Some(level) = filter.level {
+        command.arg(format!("--priority={}", level.max_priority()));
+    }
+
+    if let Some(since) = filter.since {
+        let secs = since
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        command.arg(format!("--since=@{secs}"));
+    }
+
+    // A single `--grep=` argument, so the pattern cannot be taken for another option
+    if let Some(pattern) = &filter.grep {
+        command.arg(format!("--grep={pattern}"));
+    }
+
+    let output = match filter.after_cursor {
+        Some(_) => run_head(&mut command, filter.lines as usize),
+        None => run(&mut command),
+    }
+    .context("Unable to get STDOUT")?;
+
+    // Nothing matching the pattern is not an error, only what journalctl complains about
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+
+        if filter.grep.is_some() && stderr.contains("pattern") {
+            return Err(BadPattern(stderr).into());
+        }
+
+        if filter.after_cursor.is_some() && stderr.contains("cursor") {
+            return Err(BadCursor(stderr).into());
+        }
+
+        if !stderr.is_empty() {
+            return Err(anyhow!(
+                "journalctl -u {unit} failed (status: {:?}): {stderr}",
+                output.status
+            ));
+        }
+    }
+
+    let raw =
+        String::from_utf8(output.stdout).context("Command output contains Non-UTF8 charachters")?;
+
+    raw.lines()
+        .filter(|line| !line.is_empty())
+        .take(filter.lines as usize)
+        .map(|line| LogEntry::from_json(line, timezone, redact_patterns))
+        .collect()
+}
+
+#[derive(Debug, Serialize)]
+pub struct Boot {
+    pub offset: i32,
+    pub id: String,
+    pub range: String,
+}
+
+/// Parses `journalctl --list-boots`, skipping the `IDX BOOT ID ...` header of newer versions
+pub fn journalctl_boots() -> Result<Vec<Boot>> {
+    let output = run(Command::new("journalctl")
+        .arg("--list-boots")
+        .arg("--no-pager"))
+    .context("Unable to get STDOUT")?;
+
+    let raw =
+        String::from_utf8(output.stdout).context("Command output contains Non-UTF8 charachters")?;
+
+    Ok(raw
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let offset = fields.next()?.parse::<i32>().ok()?;
+            let id = fields.next()?.to_owned();
+            let range = fields.collect::<Vec<_>>().join(" ");
+            Some(Boot { offset, id, range })
+        })
+        .filter(|boot| (MAX_BOOT_OFFSET..=0).contains(&boot.offset))
+        .collect())
+}
+
+/// Unit labels used when rendering durations
+#[derive(Debug, Clone, Copy)]
+pub struct DurationLabels {
+    pub days: &'static str,
+    pub hours: &'static str,
+    pub minutes: &'static str,
+    pub seconds: &'static str,
+}
+
+pub fn monotonic_to_system_time(monotonic_us: u64, boot_time: SystemTime) -> SystemTime {
+    boot_time + Duration::from_micros(monotonic_us)
+}
+
+pub fn monotonic_uptime(
+    monotonic_us: u64,
+    boot_time: SystemTime,
+    labels: &DurationLabels,
+) -> String {
+    let event_time = monotonic_to_system_time(monotonic_us, boot_time);
+    let now = SystemTime::now();
+    let diff = now.duration_since(event_time).unwrap_or(Duration::ZERO);
+    format_duration(diff.as_secs(), labels)
+}
+
+pub fn format_timestamp(time: SystemTime, timezone: &Tz) -> String {
+    DateTime::<Utc>::from(time)
+        .with_timezone(timezone)
+        .format("%Y-%m-%d %H:%M:%S %Z")
+        .to_string()
+}
+
+pub fn format_duration(secs: u64, labels: &DurationLabels) -> String {
+    let (days, hours, minutes, seconds) = (
+        secs / 86400,
+        (secs % 86400) / 3600,
+        (secs % 3600) / 60,
+        secs % 60,
+    );
+
+    let mut parts = vec![];
+    if days > 0 {
+        parts.push(format!("{days}{}", labels.days));
+    }
+    if hours > 0 || !parts.is_empty() {
+        parts.push(format!("{hours}{}", labels.hours));
+    }
+    if minutes > 0 || !parts.is_empty() {
+        parts.push(format!("{minutes}{}", labels.minutes));
+    }
+    parts.push(format!("{seconds}{}", labels.seconds));
+
+    parts.join(" ")
+}
+
+pub fn get_boot_time() -> std::time::SystemTime {
+    if let Some(host) = remote_host() {
+        match remote_boot_time() {
+            Ok(time) => return time,
+            Err(e) => error!("Unable to get the boot time of {host}: {e}"),
+        }
+    }
+
+    let mut sys = System::new();
+    sys.refresh_all();
+
+    let boot_time_secs = sysinfo::System::boot_time();
+
+    std::time::UNIX_EPOCH + std::time::Duration::from_secs(boot_time_secs)
+}
+
+/// When the remote host booted, as the monotonic timestamps of its units count from there
+fn remote_boot_time() -> Result<SystemTime> {
+    let output = run(Command::new("systemctl")
+        .arg("show")
+        .arg("--timestamp=unix")
+        .arg("--property")
+        .arg("KernelTimestamp")
+        .arg("--value"))
+    .context("Unable to get STDOUT")?;
+
+    let raw = String::from_utf8(output.stdout)?;
+
+    let secs = raw
+        .trim()
+        .strip_prefix('@')
+        .with_context(|| format!("Unexpected KernelTimestamp {raw:?}"))?
+        .parse::<u64>()
+        .context("Unable to parse value")?;
+
+    Ok(std::time::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// The configured description, or the unit's own `Description` when there is none
+pub fn unit_description(config: &ServiceConfig) -> String {
+    match &config.description {
+        Some(description) => description.clone(),
+        None => systemd_show_parse::<String>("Description", &config.service_name)
+            .map_err(|e| error!("Description: {e}"))
+            .unwrap_or_default(),
+    }
+}
+
+pub fn get_unit_info(unit: &Unit, config: &Config) -> Result<ServiceInfo> {
+    debug!("Unit Name: {}", unit.name);
+
+    let unit_config = config
+        .service
+        .iter()
+        .find(|a| {
+            a.service_name
+                .rsplit_once(".")
+                .map(|n| n.0 == unit.name)
+                .unwrap()
+        })
+        .with_context(|| format!("Unable to get configuration of the service {}", unit.name))?;
+
+    let state = UnitState {
+        status: format!("{:?}", unit.state),
+        active: unit.active,
+        enabled: matches!(
+            unit.auto_start,
+            AutoStartStatus::Enabled | AutoStartStatus::EnabledRuntime
+        ),
+    };
+
+    // The socket is shown by the same call
+    let mut units = vec![unit_config.service_name.clone()];
+    units.extend(unit_config.socket.clone());
+
+    let mut shown = systemctl_show_units(&units)?.into_iter();
+    let properties = shown.next().context("systemctl show printed nothing")?;
+    let socket = shown.next();
+
+    build_service_info(unit_config, &state, &properties, socket.as_ref(), config)
+}
+
+/// A unit as listed by `systemctl list-units --output=json`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ListedUnit {
+    pub unit: String,
+    pub load: String,
+    pub active: String,
+    pub sub: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// States of the given units from a single `systemctl list-units` call. Units that systemd does
+/// not know about are missing from the result
+pub fn systemctl_list_unit_states(units: &[String]) -> Result<Vec<ListedUnit>> {
+    if units.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let output = run(Command::new("systemctl")
+        .arg("list-units")
+        .arg("--all")
+        .arg("--output=json")
+        .arg("--no-pager")
+        .arg("--")
+        .args(units))
+    .context("Unable to get STDOUT")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "systemctl list-units failed (status: {:?}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    serde_json::from_slice(&output.stdout).context("Could not parse systemctl list-units output")
+}
+
+/// Every service unit systemd knows about, loaded or not, whether it is configured or not
+pub fn systemctl_list_all_services() -> Result<Vec<ListedUnit>> {
+    let output = run(Command::new("systemctl")
+        .arg("list-units")
+        .arg("--all")
+        .arg("--type=service")
+        .arg("--output=json")
+        .arg("--no-pager"))
+    .context("Unable to get STDOUT")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "systemctl list-units failed (status: {:?}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    serde_json::from_slice(&output.stdout).context("Could not parse systemctl list-units output")
+}
+
+/// Properties `build_service_info` reads
+const SERVICE_PROPERTIES: [&str; 13] = [
+    "LoadState",
+    "ActiveState",
+    "SubState",
+    "UnitFileState",
+    "MainPID",
+    "StatusErrno",
+    "ExecMainStartTimestampMonotonic",
+    "ActiveEnterTimestamp",
+    "InactiveEnterTimestampMonotonic",
+    "NRestarts",
+    "Description",
+    // Of the `.socket` units shown along with their services
+    "Listen",
+    "NConnections",
+];
+
+/// Properties of one unit, as printed by `systemctl show`. A property printed on several lines,
+/// like `Listen`, has them joined with newlines
+#[derive(Debug, Default)]
+pub struct UnitProperties(HashMap<String, String>);
+
+impl UnitProperties {
+    fn value(&self, name: &str) -> Result<&str> {
+        self.0
+            .get(name)
+            .map(String::as_str)
+            .with_context(|| format!("{name} is missing"))
+    }
+
+    fn parse<T>(&self, name: &str) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        self.value(name)?
+            .parse::<T>()
+            .with_context(|| format!("Unable to parse {name}"))
+    }
+
+    fn timestamp(&self, name: &str) -> Result<Option<SystemTime>> {
+        parse_unix_timestamp(self.value(name)?)
+    }
+}
+
+/// The `SERVICE_PROPERTIES` of every unit of `units` from a single `systemctl show` call, in the
+/// same order
+pub fn systemctl_show_units(units: &[String]) -> Result<Vec<UnitProperties>> {
+    if units.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let output = run(Command::new("systemctl")
+        .arg("show")
+        .arg("--timestamp=unix")
+        .arg(format!("--property={}", SERVICE_PROPERTIES.join(",")))
+        .arg("--")
+        .args(units))
+    .context("Unable to get STDOUT")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "systemctl show failed (status: {:?}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let blocks = parse_show_blocks(&String::from_utf8_lossy(&output.stdout));
+
+    // Matched by position, as `Id` is the real name of a unit given by one of its aliases
+    if blocks.len() != units.len() {
+        return Err(anyhow!(
+            "systemctl show printed {} units instead of {}",
+            blocks.len(),
+            units.len()
+        ));
+    }
+
+    Ok(blocks)
+}
+
+/// Splits the output of `systemctl show` for several units: one block of `Key=value` lines per
+/// unit, separated by blank lines
+fn parse_show_blocks(raw: &str) -> Vec<UnitProperties> {
+    raw.split("\n\n")
+        .map(|block| {
+            let mut properties = HashMap::new();
+            for (key, value) in block.lines().filter_map(|line| line.split_once('=')) {
+                properties
+                    .entry(key.to_owned())
+                    .and_modify(|values: &mut String| {
+                        values.push('\n');
+                        values.push_str(value);
+                    })
+                    .or_insert_with(|| value.to_owned());
+            }
+            UnitProperties(properties)
+        })
+        .filter(|properties| !properties.0.is_empty())
+        .collect()
+}
+
+/// Builds the card of a service from the properties `systemctl_show_units` read for it and for
+/// its socket, without running anything else
+pub fn get_shown_unit_info(
+    service: &ServiceConfig,
+    properties: &UnitProperties,
+    socket: Option<&UnitProperties>,
+    config: &Config,
+) -> Result<ServiceInfo> {
+    let load = properties.value("LoadState")?;
+
+    if load == "not-found" {
+        return Ok(ServiceInfo::not_found(service.clone()));
+    }
+
+    let state = UnitState {
+        status: load_status(load),
+        active: properties.value("ActiveState")? == "active",
+        enabled: matches!(
+            properties.value("UnitFileState").unwrap_or_default(),
+            "enabled" | "enabled-runtime"
+        ),
+    };
+
+    build_service_info(service, &state, properties, socket, config)
+}
+
+/// Only the state `list-units` reports, without running anything else. The other fields are left
+/// empty
+pub fn get_listed_unit_state(service: &ServiceConfig, listed: &ListedUnit) -> ServiceInfo {
+    if listed.load == "not-found" {
+        return ServiceInfo::not_found(service.clone());
+    }
+
+    let (running, exited) = run_state(&listed.active, &listed.sub);
+
+    ServiceInfo {
+        status: load_status(&listed.load),
+        active: listed.active == "active",
+        running,
+        exited,
+        failed: listed.active == "failed",
+        unavailable: false,
+        ..ServiceInfo::unavailable(service.clone())
+    }
+}
+
+/// `(running, exited)` of a unit in `active_state` and `sub_state`. MainPID is 0 for oneshot and
+/// some bus-activated units even while they are active, so the state decides. A oneshot unit that
+/// finished is `active (exited)`: active, but without processes
+fn run_state(active_state: &str, sub_state: &str) -> (bool, bool) {
+    let exited = active_state == "active" && sub_state == "exited";
+    let running = matches!(active_state, "active" | "reloading") && !exited;
+    (running, exited)
+}
+
+/// Same spelling as the `State` of the systemctl crate
+fn load_status(load: &str) -> String {
+    match load {
+        "loaded" => "Loaded".to_owned(),
+        "masked" => "Masked".to_owned(),
+        other => other.to_owned(),
+    }
+}
+
+struct UnitState {
+    status: String,
+    active: bool,
+    enabled: bool,
+}
+
+fn build_service_info(
+    unit_config: &ServiceConfig,
+    unit: &UnitState,
+    properties: &UnitProperties,
+    socket: Option<&UnitProperties>,
+    config: &Config,
+) -> Result<ServiceInfo> {
+    let main_pid = properties.parse::<u64>("MainPID").ok();
+
+    let status_code = properties
+        .parse::<u8>("StatusErrno")
+        .map_err(|e| error!("StatusCode: {e}"))
+        .ok();
+
+    // Empty for some unit types and 0 when the main process never ran, which only leaves the
+    // uptime out of the card
+    let uptime = properties
+        .parse::<u64>("ExecMainStartTimestampMonotonic")
+        .map_err(|e| {
+            debug!(
+                "ExecMainStartTimestampMonotonic of {}: {e}",
+                unit_config.service_name
+            )
+        })
+        .ok()
+        .filter(|&monotonic| monotonic > 0);
+
+    let active_state = properties.value("ActiveState").unwrap_or_default();
+    let sub_state = properties.value("SubState").unwrap_or_default();
+
+    let failed = active_state == "failed";
+    let (running, exited) = run_state(active_state, sub_state);
+
+    let restarts = properties.parse::<u32>("NRestarts").unwrap_or(0);
+
+    let boot_time = get_boot_time();
+
+    let pretty_uptime = uptime
+        .map(|uptime| monotonic_uptime(uptime, boot_time, &config.locale.duration_labels()))
+        .unwrap_or_default();
+
+    // 0 when the unit never stopped since boot
+    let down_for = match unit.active {
+        true => String::new(),
+        false => properties
+            .parse::<u64>("InactiveEnterTimestampMonotonic")
+            .map_err(|e| error!("InactiveEnterTimestampMonotonic: {e}"))
+            .ok()
+            .filter(|&monotonic| monotonic > 0)
+            .map(|monotonic| {
+                monotonic_uptime(monotonic, boot_time, &config.locale.duration_labels())
+            })
+            .unwrap_or_default(),
+    };
+
+    let started = uptime
+        .map(|uptime| {
+            format_timestamp(
+                monotonic_to_system_time(uptime, boot_time),
+                &config.timezone,
+            )
+        })
+        .unwrap_or_default();
+
+    let active_since = match unit.active {
+        true => properties
+            .timestamp("ActiveEnterTimestamp")
+            .map_err(|e| error!("ActiveEnterTimestamp: {e}"))
+            .ok()
+            .flatten()
+            .map(|time| format_timestamp(time, &config.timezone))
+            .unwrap_or_default(),
+        false => String::new(),
+    };
+
+    let description = match &unit_config.description {
+        Some(description) => description.clone(),
+        None => properties
+            .value("Description")
+            .unwrap_or_default()
+            .to_owned(),
+    };
+
+    let socket = unit_config
+        .socket
+        .as_deref()
+        .zip(socket)
+        .map(|(socket, properties)| socket_info(socket, properties));
+
+    Ok(ServiceInfo {
+        config: unit_config.clone(),
+        status: unit.status.clone(),
+        active: unit.active,
+        enabled: unit.enabled,
+        running,
+        exited,
+        pid: main_pid,
+        status_code,
+        uptime: pretty_uptime,
+        down_for,
+        started,
+        active_since,
+        failed,
+        restarts,
+        description,
+        unavailable: false,
+        not_found: false,
+        host: None,
+        availability: None,
+        socket,
+        over_budget: None,
+    })
+}
+
+/// Memory and CPU time used by a unit, `None` when its accounting is off
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceUsage {
+    pub memory_bytes: Option<u64>,
+    pub cpu_nsec: Option<u64>,
+}
+
+/// Reads `MemoryCurrent` and `CPUUsageNSec` with a single `systemctl show` call
+pub fn resource_usage(unit: &str) -> Result<ResourceUsage> {
+    let output = run(Command::new("systemctl")
+        .arg("show")
+        .arg(unit)
+        .arg("--property=MemoryCurrent,CPUUsageNSec"))
+    .context("Unable to get STDOUT")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "systemctl failed (status: {:?}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    // `[not set]`, or the maximum value when the accounting is off
+    let mut usage = ResourceUsage {
+        memory_bytes: None,
+        cpu_nsec: None,
+    };
+
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let value = |value: &str| value.parse::<u64>().ok().filter(|&v| v != u64::MAX);
+
+        match line.split_once('=') {
+            Some(("MemoryCurrent", v)) => usage.memory_bytes = value(v),
+            Some(("CPUUsageNSec", v)) => usage.cpu_nsec = value(v),
+            _ => {}
+        }
+    }
+
+    Ok(usage)
+}
+
+/// State of the `.socket` unit that activates a service
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SocketInfo {
+    pub unit: String,
+    pub active_state: String,
+    /// Addresses it listens on, e.g. `[::]:22 (Stream)`
+    pub listen: Vec<String>,
+    /// Connections open right now. Only counted for `Accept=yes` sockets
+    pub connections: u32,
+}
+
+/// State of `socket` from the properties `systemctl_show_units` read for it
+pub fn socket_info(socket: &str, properties: &UnitProperties) -> SocketInfo {
+    SocketInfo {
+        unit: socket.to_owned(),
+        active_state: properties
+            .value("ActiveState")
+            .unwrap_or_default()
+            .to_owned(),
+        listen: properties
+            .value("Listen")
+            .map(|listen| listen.lines().map(str::to_owned).collect())
+            .unwrap_or_default(),
+        connections: properties.parse("NConnections").unwrap_or(0),
+    }
+}
+
+/// Properties `get_unit_info` and friends read, dumped by `check_unit`
+const INFO_PROPERTIES: [&str; 12] = [
+    "LoadState",
+    "ActiveState",
+    "SubState",
+    "UnitFileState",
+    "MainPID",
+    "StatusErrno",
+    "ExecMainStartTimestampMonotonic",
+    "ActiveEnterTimestamp",
+    "InactiveEnterTimestampMonotonic",
+    "NRestarts",
+    "Description",
+    "FragmentPath",
+];
+
+/// Report of how `name` is mapped between the config and systemd, for `--check-unit`
+pub fn check_unit(systemctl: &SystemCtl, config: &Config, name: &str) -> String {
+    let mut report = format!("Unit: {name}\n\n");
+
+    match config.service.iter().find(|s| s.service_name == name) {
+        Some(service) => {
+            report += "Configuration entry:\n";
+            report += &toml::to_string(service).unwrap_or_else(|e| format!("{e}\n"));
+        }
+        None => report += "Configuration entry: none, no service has this service_name\n",
+    }
+
+    report += "\nsystemctl crate unit:\n";
+
+    let unit = match systemctl.create_unit(name) {
+        Ok(unit) => {
+            report += &format!(
+                "name = {}\nstate = {:?}\nactive = {}\nauto_start = {:?}\n",
+                unit.name, unit.state, unit.active, unit.auto_start
+            );
+            Some(unit)
+        }
+        Err(e) => {
+            report += &format!("could not be created: {e}\n");
+            None
+        }
+    };
+
+    let properties: Vec<String> = INFO_PROPERTIES
+        .iter()
+        .map(|p| p.to_string())
+        .chain(config.extra_properties.iter().cloned())
+        .collect();
+
+    report += "\nsystemctl show:\n";
+
+    match systemd_show_properties(name, &properties) {
+        Ok(values) => {
+            for (property, value) in values {
+                report += &format!("{property}={value}\n");
+            }
+        }
+        Err(e) => report += &format!("failed: {e:#}\n"),
+    }
+
+    if let Some(unit) = unit {
+        report += "\nget_unit_info: ";
+        match get_unit_info(&unit, config) {
+            Ok(_) => report += "ok\n",
+            Err(e) => report += &format!("{e:#}\n"),
+        }
+    }
+
+    report
+}
+
+/// Properties read for the detailed view on top of the configured `extra_properties`
+const DETAIL_PROPERTIES: [&str; 16] = [
+    "Type",
+    "Restart",
+    "ControlGroup",
+    "FragmentPath",
+    "Transient",
+    "SourcePath",
+    "Environment",
+    "EnvironmentFiles",
+    "WorkingDirectory",
+    "MemoryCurrent",
+    "Requires",
+    "Wants",
+    "ConditionResult",
+    "AssertResult",
+    "ConditionTimestamp",
+    "AssertTimestamp",
+];
+
+/// Whether the conditions (or assertions) of a unit failed the last time it was started, from its
+/// `ConditionResult` and `ConditionTimestamp`. The result is `no` too for a unit that was never
+/// started, which has no timestamp
+fn check_failed(result: &str, timestamp: &str) -> bool {
+    result == "no" && !matches!(timestamp, "" | "n/a")
+}
+
+/// The `Condition*=` and `Assert*=` settings of `unit` that were not met the last time it was
+/// started, in words. Reads lines like `Conditions=ConditionPathExists=/etc/app.conf -1`, where a
+/// negative state means it failed, and falls back to a generic reason for anything else
+fn unmet_conditions(unit: &str) -> String {
+    let output = run(Command::new("systemctl")
+        .arg("show")
+        .arg(unit)
+        .arg("--property=Conditions,Asserts"));
+
+    let unmet: Vec<String> = output
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (kind, value) = line.split_once('=')?;
+            let (setting, state) = value.rsplit_once(' ')?;
+            let state: i32 = state.trim().parse().ok()?;
+
+            (state < 0).then(|| match kind {
+                "Asserts" => format!("assertion {setting} failed"),
+                _ => format!("condition {setting} not met"),
+            })
+        })
+        .collect();
+
+    match unmet.is_empty() {
+        true => "Skipped: a start condition was not met".to_owned(),
+        false => format!("Skipped: {}", unmet.join(", ")),
+    }
+}
+
+/// `bytes` in the largest binary unit that keeps it above 1, e.g. `12.3 MiB`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{value:.1} {}", UNITS[unit]),
+    }
+}
+
+/// What `Type=` and `Restart=` mean for the state shown on the card, in plain words
+fn explain_behavior(r#type: &str, restart: &str) -> Vec<String> {
+    let r#type = match r#type {
+        "simple" | "exec" => "Runs until its process exits and is up as soon as it is started.",
+        "forking" => "Starts a background process and is up once the starting one exits.",
+        "oneshot" => {
+            "Runs once and exits. Being inactive (dead) after it finished successfully is normal."
+        }
+        "notify" | "notify-reload" => "Tells systemd when it is ready and is up from then on.",
+        "dbus" => "Is up once it takes its name on the D-Bus.",
+        "idle" => "Runs like a simple service, but only once the boot jobs are done.",
+        _ => return Vec::new(),
+    };
+
+    let restart = match restart {
+        "no" | "" => "Not restarted when it stops.",
+        "always" => "Restarted whenever it stops, even after a clean exit.",
+        "on-failure" => "Restarted when it fails, crashes or times out.",
+        "on-abnormal" => "Restarted when it crashes or times out, but not on an error exit.",
+        "on-abort" => "Restarted when it is killed by an unexpected signal.",
+        "on-watchdog" => "Restarted when its watchdog times out.",
+        "on-success" => "Restarted only after a clean exit.",
+        _ => "",
+    };
+
+    [r#type, restart]
+        .into_iter()
+        .filter(|sentence| !sentence.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Variables whose name ends with one of these have their value hidden
+const SECRET_SUFFIXES: [&str; 6] = [
+    "TOKEN",
+    "PASSWORD",
+    "PASSWD",
+    "SECRET",
+    "KEY",
+    "CREDENTIALS",
+];
+
+/// Splits the `Environment` property (`A=1 "B=two words"`) into names and values, hiding the
+/// values of variables that look like secrets
+fn parse_environment(raw: &str) -> Vec<(String, String)> {
+    let mut assignments = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in raw.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    assignments.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+
+    if !current.is_empty() {
+        assignments.push(current);
+    }
+
+    assignments
+        .into_iter()
+        .filter_map(|assignment| {
+            let (name, value) = assignment.split_once('=')?;
+            let secret = SECRET_SUFFIXES
+                .iter()
+                .any(|suffix| name.to_ascii_uppercase().ends_with(suffix));
+            let value = match secret {
+                true => "********".to_owned(),
+                false => value.to_owned(),
+            };
+            Some((name.to_owned(), value))
+        })
+        .collect()
+}
+
+/// The configured `extra_properties` as shown, with `redact_patterns` hidden in their values. An
+/// `Environment` property gets the secrets of its variables hidden like the environment of the
+/// detailed view
+fn shown_properties(
+    properties: Vec<(String, String)>,
+    redact_patterns: &[Regex],
+) -> Vec<(String, String)> {
+    properties
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match name.as_str() {
+                "Environment" => format_environment(&parse_environment(&value)),
+                _ => value,
+            };
+            let value = redact(&value, redact_patterns).into_owned();
+            (name, value)
+        })
+        .collect()
+}
+
+/// `variables` the way systemd prints them, quoting the values with spaces
+fn format_environment(variables: &[(String, String)]) -> String {
+    variables
+        .iter()
+        .map(|(name, value)| match value.contains(char::is_whitespace) {
+            true => format!("\"{name}={value}\""),
+            false => format!("{name}={value}"),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Directories systemd generators write their units to
+const GENERATOR_DIRS: [&str; 3] = [
+    "/run/systemd/generator/",
+    "/run/systemd/generator.early/",
+    "/run/systemd/generator.late/",
+];
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// A process of a unit, or with no `pid` one of its child cgroups
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProcessNode {
+    depth: usize,
+    pid: Option<u32>,
+    name: String,
+}
+
+/// The processes in `control_group` and its children, as `systemctl status` lists them under
+/// `CGroup:`. Command lines can carry secrets too, `redact_patterns` are hidden in them
+fn cgroup_tree(control_group: &str, redact_patterns: &[Regex]) -> Result<Vec<ProcessNode>> {
+    let root = Path::new(CGROUP_ROOT).join(control_group.trim_start_matches('/'));
+    let mut nodes = Vec::new();
+
+    walk_cgroup(&root, 0, redact_patterns, &mut nodes)
+        .with_context(|| format!("Unable to read {}", root.display()))?;
+
+    Ok(nodes)
+}
+
+fn walk_cgroup(
+    dir: &Path,
+    depth: usize,
+    redact_patterns: &[Regex],
+    nodes: &mut Vec<ProcessNode>,
+) -> std::io::Result<()> {
+    let procs = std::fs::read_to_string(dir.join("cgroup.procs"))?;
+
+    for pid in procs
+        .lines()
+        .filter_map(|line| line.trim().parse::<u32>().ok())
+    {
+        // The process may exit while the tree is read
+        if let Some(command) = process_command(pid) {
+            nodes.push(ProcessNode {
+                depth,
+                pid: Some(pid),
+                name: redact(&command, redact_patterns).into_owned(),
+            });
+        }
+    }
+
+    let mut children: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|entry| entry.path())
+        .collect();
+    children.sort();
+
+    for child in children {
+        nodes.push(ProcessNode {
+            depth,
+            pid: None,
+            name: child
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        });
+        walk_cgroup(&child, depth + 1, redact_patterns, nodes)?;
+    }
+
+    Ok(())
+}
+
+/// Command line of `pid`, or its name for kernel threads, which have none
+fn process_command(pid: u32) -> Option<String> {
+    let cmdline = std::fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+
+    let command = shown_command(&cmdline);
+
+    match command.is_empty() {
+        true => std::fs::read_to_string(format!("/proc/{pid}/comm"))
+            .ok()
+            .map(|comm| format!("[{}]", comm.trim())),
+        false => Some(command),
+    }
+}
+
+/// The NUL separated arguments of `cmdline` joined with spaces. The values of arguments named
+/// like a secret, `--db-password=x`, `--api-token x` or `TOKEN=x`, are hidden
+fn shown_command(cmdline: &[u8]) -> String {
+    let is_secret = |name: &str| {
+        let name = name
+            .trim_start_matches('-')
+            .replace('-', "_")
+            .to_ascii_uppercase();
+        SECRET_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+    };
+
+    let mut hide_next = false;
+
+    cmdline
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .map(|arg| {
+            if std::mem::take(&mut hide_next) {
+                return "********".to_owned();
+            }
+
+            match arg.split_once('=') {
+                Some((name, _)) if is_secret(name) => format!("{name}=********"),
+                Some(_) => arg.into_owned(),
+                None => {
+                    hide_next = arg.starts_with("--") && is_secret(&arg);
+                    arg.into_owned()
+                }
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn get_unit_detail(unit: &Unit, config: &Config) -> Result<ServiceDetail> {
+    let info = get_unit_info(unit, config)?;
+
+    let requested: Vec<String> = DETAIL_PROPERTIES
+        .iter()
+        .map(|p| p.to_string())
+        .chain(config.extra_properties.iter().cloned())
+        .collect();
+
+    let mut properties = systemd_show_properties(&info.config.service_name, &requested)?;
+
+    let extra = shown_properties(
+        properties.split_off(DETAIL_PROPERTIES.len()),
+        &config.redact_regexes,
+    );
+    let mut detail = properties.into_iter().map(|(_, value)| value);
+
+    let r#type = detail.next().unwrap_or_default();
+    let restart = detail.next().unwrap_or_default();
+    let control_group = detail.next().unwrap_or_default();
+    let unit_file = detail.next().unwrap_or_default();
+    let transient = detail.next().is_some_and(|value| value == "yes");
+    let source_path = detail.next().unwrap_or_default();
+    let environment = parse_environment(&detail.next().unwrap_or_default());
+    let environment_files = detail.next().unwrap_or_default();
+    let working_directory = detail.next().unwrap_or_default();
+    // `[not set]`, or the maximum value when memory accounting is off
+    let memory = detail
+        .next()
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|&bytes| bytes != u64::MAX)
+        .map(format_bytes)
+        .unwrap_or_default();
+    let dependencies = detail
+        .by_ref()
+        .take(2)
+        .flat_map(|value| {
+            value
+                .split_whitespace()
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let condition_result = detail.next().unwrap_or_default();
+    let assert_result = detail.next().unwrap_or_default();
+    let condition_timestamp = detail.next().unwrap_or_default();
+    let assert_timestamp = detail.next().unwrap_or_default();
+
+    // An inactive unit whose condition failed was skipped on purpose, it did not fail
+    let skipped = match !info.active
+        && (check_failed(&condition_result, &condition_timestamp)
+            || check_failed(&assert_result, &assert_timestamp))
+    {
+        true => Some(unmet_conditions(&info.config.service_name)),
+        false => None,
+    };
+
+    let generated = GENERATOR_DIRS.iter().any(|dir| unit_file.starts_with(dir));
+
+    // The cgroup hierarchy is read from the local filesystem
+    let processes = match control_group.is_empty() || remote_host().is_some() {
+        true => Vec::new(),
+        false => cgroup_tree(&control_group, &config.redact_regexes).unwrap_or_else(|e| {
+            warn!(
+                "Unable to read the cgroup of {}: {e}",
+                info.config.service_name
+            );
+            Vec::new()
+        }),
+    };
+
+    Ok(ServiceDetail {
+        config: info.config,
+        status: info.status,
+        active: info.active,
+        enabled: info.enabled,
+        running: info.running,
+        pid: info.pid,
+        status_code: info.status_code,
+        uptime: info.uptime,
+        active_since: info.active_since,
+        behavior: explain_behavior(&r#type, &restart),
+        r#type,
+        restart,
+        unit_file,
+        transient,
+        generated,
+        source_path,
+        environment,
+        environment_files,
+        working_directory,
+        memory,
+        dependencies,
+        skipped,
+        processes,
+        configuration: String::new(),
+        properties: extra,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(pairs: &[(&str, &str)]) -> UnitProperties {
+        UnitProperties(
+            pairs
+                .iter()
+                .map(|&(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+        )
+    }
+
+    fn service() -> ServiceConfig {
+        toml::from_str("service_name = \"demo.service\"\nfriendly_name = \"Demo\"").unwrap()
+    }
+
+    fn config() -> Config {
+        toml::from_str("").unwrap()
+    }
+
+    #[test]
+    fn durations_are_split_at_unit_boundaries() {
+        let labels = config().locale.duration_labels();
+
+        let cases = [
+            (0, "0s"),
+            (59, "59s"),
+            (60, "1m 0s"),
+            (3599, "59m 59s"),
+            (3600, "1h 0m 0s"),
+            (86399, "23h 59m 59s"),
+            (86400, "1d 0h 0m 0s"),
+            (90061, "1d 1h 1m 1s"),
+        ];
+
+        for (secs, formatted) in cases {
+            assert_eq!(format_duration(secs, &labels), formatted, "{secs}s");
+        }
+    }
+
+    #[test]
+    fn sub_second_uptimes_are_zero_seconds() {
+        let labels = config().locale.duration_labels();
+        let boot_time = SystemTime::now() - Duration::from_millis(500);
+
+        assert_eq!(monotonic_uptime(0, boot_time, &labels), "0s");
+        // Later than now, e.g. after the clock was set back
+        assert_eq!(monotonic_uptime(60_000_000, boot_time, &labels), "0s");
+    }
+
+    #[test]
+    fn state_decides_running_and_exited() {
+        // (what, ActiveState, SubState, running, exited)
+        let cases = [
+            ("oneshot that finished", "active", "exited", false, true),
+            (
+                "bus-activated without MainPID",
+                "active",
+                "running",
+                true,
+                false,
+            ),
+            ("reloading", "reloading", "reload", true, false),
+            ("failed", "failed", "failed", false, false),
+            ("stopped", "inactive", "dead", false, false),
+        ];
+
+        for (what, active, sub, running, exited) in cases {
+            assert_eq!(run_state(active, sub), (running, exited), "{what}");
+
+            let listed = ListedUnit {
+                unit: "demo.service".to_owned(),
+                load: "loaded".to_owned(),
+                active: active.to_owned(),
+                sub: sub.to_owned(),
+                description: String::new(),
+            };
+            let service: ServiceConfig =
+                toml::from_str("service_name = \"demo.service\"\nfriendly_name = \"Demo\"")
+                    .unwrap();
+            let info = get_listed_unit_state(&service, &listed);
+
+            assert_eq!((info.running, info.exited), (running, exited), "{what}");
+            assert_eq!(info.failed, active == "failed", "{what}");
+        }
+    }
+
+    #[test]
+    fn redact_hides_every_match() {
+        let patterns = [
+            Regex::new(r"password=\S+").unwrap(),
+            Regex::new(r"Bearer [A-Za-z0-9]+").unwrap(),
+        ];
+
+        let line = "login password=hunter2 then Bearer abc123 and password=again";
+        let redacted = redact(line, &patterns);
+
+        assert!(!redacted.contains("hunter2"), "{redacted}");
+        assert!(!redacted.contains("abc123"), "{redacted}");
+        assert!(!redacted.contains("again"), "{redacted}");
+        assert_eq!(redacted, "login *** then *** and ***");
+
+        // Untouched lines are not copied
+        assert!(matches!(
+            redact("nothing here", &patterns),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn environment_is_split_and_its_secrets_hidden() {
+        let environment = parse_environment(
+            r#"LANG=C "GREETING=hello world" API_TOKEN=abc "DB_PASSWORD=a b" EMPTY="#,
+        );
+
+        assert_eq!(
+            environment,
+            [
+                ("LANG".to_owned(), "C".to_owned()),
+                ("GREETING".to_owned(), "hello world".to_owned()),
+                ("API_TOKEN".to_owned(), "********".to_owned()),
+                ("DB_PASSWORD".to_owned(), "********".to_owned()),
+                ("EMPTY".to_owned(), String::new()),
+            ]
+        );
+
+        let properties = shown_properties(
+            vec![(
+                "Environment".to_owned(),
+                r#"LANG=C "GREETING=hello world" api_key=abc"#.to_owned(),
+            )],
+            &[],
+        );
+        assert_eq!(
+            properties[0].1,
+            r#"LANG=C "GREETING=hello world" api_key=********"#
+        );
+    }
+
+    #[test]
+    fn unit_file_environment_secrets_are_hidden() {
+        let unit_file = unit_file_html(
+            "[Service]\nEnvironment=TOKEN=s3cret LANG=C\nEnvironment=\"DB_PASSWORD=two words\"\nExecStart=/usr/bin/demo\n",
+            &[],
+        );
+
+        assert!(!unit_file.contains("s3cret"), "{unit_file}");
+        assert!(!unit_file.contains("two words"), "{unit_file}");
+        assert!(
+            unit_file.contains("Environment=TOKEN=******** LANG=C"),
+            "{unit_file}"
+        );
+        assert!(
+            unit_file.contains("Environment=DB_PASSWORD=********"),
+            "{unit_file}"
+        );
+        assert!(unit_file.contains("ExecStart=/usr/bin/demo"), "{unit_file}");
+    }
+
+    #[test]
+    fn secret_arguments_are_hidden_from_command_lines() {
+        let cmdline = b"/usr/bin/db\0--db-password=hunter2\0--api-token\0abc\0API_KEY=xyz\0--port=5432\0--verbose\0data\0";
+
+        assert_eq!(
+            shown_command(cmdline),
+            "/usr/bin/db --db-password=******** --api-token ******** API_KEY=******** --port=5432 --verbose data"
+        );
+    }
+
+    #[test]
+    fn unit_derived_text_is_redacted() {
+        let patterns = [Regex::new(r"password=\S+").unwrap()];
+
+        let properties = shown_properties(
+            vec![
+                (
+                    "ExecStart".to_owned(),
+                    "/usr/bin/db password=hunter2".to_owned(),
+                ),
+                ("Nice".to_owned(), "0".to_owned()),
+            ],
+            &patterns,
+        );
+        assert_eq!(
+            properties,
+            [
+                ("ExecStart".to_owned(), "/usr/bin/db ***".to_owned()),
+                ("Nice".to_owned(), "0".to_owned()),
+            ]
+        );
+
+        let unit_file = unit_file_html(
+            "[Service]\nExecStart=/usr/bin/db password=hunter2\n",
+            &patterns,
+        );
+        assert!(!unit_file.contains("hunter2"), "{unit_file}");
+        assert!(
+            unit_file.contains("ExecStart=/usr/bin/db ***"),
+            "{unit_file}"
+        );
+
+        // A cgroup with this process in it, whose command line is read from /proc
+        let dir =
+            std::env::temp_dir().join(format!("daemon-manager-cgroup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("cgroup.procs"), std::process::id().to_string()).unwrap();
+
+        let mut nodes = Vec::new();
+        let walked = walk_cgroup(&dir, 0, &[Regex::new(".+").unwrap()], &mut nodes);
+        std::fs::remove_dir_all(&dir).unwrap();
+        walked.unwrap();
+
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].name, "***");
+    }
+
+    #[test]
+    fn journal_messages_are_redacted() {
+        let patterns = [Regex::new(r"password=\S+").unwrap()];
+        let line = r#"{"__REALTIME_TIMESTAMP":"1700000000000000","MESSAGE":"db password=hunter2 ok","PRIORITY":"6","__CURSOR":"s=1"}"#;
+
+        let entry = LogEntry::from_json(line, &Tz::UTC, &patterns).unwrap();
+
+        assert_eq!(entry.message, "db *** ok");
+    }
+
+    #[test]
+    fn ansi_html_escapes_markup() {
+        let output = "\x1b[1;31mfailed\x1b[0m <script>alert(1)</script> \x1b[38;5m\x1b]8;;x \x1b[";
+
+        // Only the tags of the styles are markup
+        let html = ansi_html(output);
+        let without_styles = Regex::new(r"</?(span|b|i|u|s)( [^>]*)?>")
+            .unwrap()
+            .replace_all(&html, "")
+            .into_owned();
+        assert!(!without_styles.contains('<'), "{html}");
+        assert!(html.contains("&lt;script&gt;"), "{html}");
+
+        let plain = plain_html(output);
+        assert!(!plain.contains('<'), "{plain}");
+        assert!(!plain.contains('\x1b'), "{plain}");
+        assert!(plain.contains("&lt;script&gt;"), "{plain}");
+    }
+
+    #[test]
+    fn parse_show_blocks_keeps_units_apart() {
+        // What `systemctl show` prints for three units, the second of which does not exist
+        let raw = "LoadState=loaded\n\
+                   ActiveState=active\n\
+                   Description=First\n\
+                   \n\
+                   LoadState=not-found\n\
+                   ActiveState=inactive\n\
+                   Description=second.service\n\
+                   \n\
+                   LoadState=loaded\n\
+                   ActiveState=failed\n\
+                   Description=Third\n";
+
+        let blocks = parse_show_blocks(raw);
+
+        assert_eq!(blocks.len(), 3);
+
+        let described: Vec<(&str, &str, &str)> = blocks
+            .iter()
+            .map(|b| {
+                (
+                    b.value("LoadState").unwrap(),
+                    b.value("ActiveState").unwrap(),
+                    b.value("Description").unwrap(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            described,
+            [
+                ("loaded", "active", "First"),
+                ("not-found", "inactive", "second.service"),
+                ("loaded", "failed", "Third"),
+            ]
+        );
+
+        // Nothing from the next block leaks into the previous one
+        assert!(blocks.iter().all(|b| b.0.len() == 3));
+    }
+
+    #[test]
+    fn unknown_start_time_leaves_the_uptime_empty() {
+        for start in ["", "0"] {
+            let shown = properties(&[
+                ("LoadState", "loaded"),
+                ("ActiveState", "active"),
+                ("SubState", "exited"),
+                ("UnitFileState", "enabled"),
+                ("MainPID", "0"),
+                ("StatusErrno", "0"),
+                ("ExecMainStartTimestampMonotonic", start),
+                ("ActiveEnterTimestamp", ""),
+                ("InactiveEnterTimestampMonotonic", "0"),
+                ("NRestarts", "0"),
+                ("Description", "Demo"),
+            ]);
+
+            let info = get_shown_unit_info(&service(), &shown, None, &config()).unwrap();
+
+            assert_eq!(info.uptime, "", "start time {start:?}");
+            assert_eq!(info.started, "", "start time {start:?}");
+            assert!(info.active);
+        }
+    }
+
+    #[test]
+    fn units_systemd_does_not_know_are_not_problems() {
+        let shown = properties(&[("LoadState", "not-found"), ("ActiveState", "inactive")]);
+
+        let info = get_shown_unit_info(&service(), &shown, None, &config()).unwrap();
+
+        assert!(info.not_found);
+        assert!(!info.unavailable);
+        assert!(!info.is_problem());
+    }
+
+    #[test]
+    fn sockets_are_read_from_the_batched_show() {
+        let shown = parse_show_blocks(
+            "LoadState=loaded\nActiveState=active\nSubState=running\nUnitFileState=enabled\n\
+             MainPID=0\nStatusErrno=0\nExecMainStartTimestampMonotonic=0\nActiveEnterTimestamp=\n\
+             InactiveEnterTimestampMonotonic=0\nNRestarts=0\nDescription=Demo\n\n\
+             ActiveState=active\nListen=[::]:22 (Stream)\nListen=/run/demo.sock (Stream)\n\
+             NConnections=2\n",
+        );
+
+        let mut service = service();
+        service.socket = Some("demo.socket".to_owned());
+
+        let info = get_shown_unit_info(&service, &shown[0], shown.get(1), &config()).unwrap();
+        let socket = info.socket.unwrap();
+
+        assert_eq!(socket.unit, "demo.socket");
+        assert_eq!(socket.active_state, "active");
+        assert_eq!(
+            socket.listen,
+            ["[::]:22 (Stream)", "/run/demo.sock (Stream)"]
+        );
+        assert_eq!(socket.connections, 2);
+    }
+
+    #[test]
+    fn the_systemctl_crate_runs_under_timeout() {
+        let mut config = config();
+        config.systemctl_path = PathBuf::from("/usr/bin/systemctl");
+
+        let systemctl = format!("{:?}", build_systemctl(&config, Some("admin@web-1")));
+
+        assert!(
+            systemctl.contains(r#"path: Some("timeout")"#),
+            "{systemctl}"
+        );
+        assert!(
+            systemctl
+                .contains(r#"["--signal=KILL", "10s", "/usr/bin/systemctl", "-H", "admin@web-1"]"#),
+            "{systemctl}"
+        );
+    }
+
+    #[tokio::test]
+    async fn subprocess_slots_bound_the_blocking_tasks() {
+        set_subprocess_limit(1, Duration::from_secs(60), 0);
+
+        let running = Arc::new(AtomicU64::new(0));
+        let most = Arc::new(AtomicU64::new(0));
+
+        let tasks: Vec<_> = (0..4)
+            .map(|_| {
+                let (running, most) = (running.clone(), most.clone());
+                tokio::spawn(spawn_blocking_limited(move || {
+                    most.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                }))
+            })
+            .collect();
+
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn run_head_stops_the_command_after_enough_lines() {
+        let output = run_head(&mut Command::new("yes"), 3).unwrap();
+
+        assert_eq!(output.stdout, b"y\ny\ny\n");
+    }
+
+    #[test]
+    fn dependency_trees_are_parsed_in_both_glyph_sets() {
+        // `systemctl list-dependencies --full cron.service` in a UTF-8 locale...
+        let utf8 = "cron.service\n\
+                    ● ├─system.slice\n\
+                    ● └─sysinit.target\n\
+                    ●   ├─apparmor.service\n\
+                    ○   ├─dev-mqueue.mount\n\
+                    ●   ├─local-fs.target\n\
+                    ●   │ ├─-.mount\n\
+                    ×   │ └─boot-efi.mount\n\
+                    ●   └─swap.target\n";
+
+        // ...and in the C locale
+        let ascii = "cron.service\n\
+                     * |-system.slice\n\
+                     * `-sysinit.target\n\
+                     *   |-apparmor.service\n\
+                     *   |-dev-mqueue.mount\n\
+                     *   |-local-fs.target\n\
+                     *   | |--.mount\n\
+                     x   | `-boot-efi.mount\n\
+                     *   `-swap.target\n";
+
+        let node = |unit: &str, children: Vec<DependencyNode>| DependencyNode {
+            children,
+            ..dependency_node(unit)
+        };
+
+        let expected = node(
+            "cron.service",
+            vec![
+                node("system.slice", vec![]),
+                node(
+                    "sysinit.target",
+                    vec![
+                        node("apparmor.service", vec![]),
+                        node("dev-mqueue.mount", vec![]),
+                        node(
+                            "local-fs.target",
+                            vec![node("-.mount", vec![]), node("boot-efi.mount", vec![])],
+                        ),
+                        node("swap.target", vec![]),
+                    ],
+                ),
+            ],
+        );
+
+        assert_eq!(parse_dependency_tree(utf8).unwrap(), expected);
+        assert_eq!(parse_dependency_tree(ascii).unwrap(), expected);
+    }
+
+    #[test]
+    fn only_a_failed_start_condition_is_a_skip() {
+        // (what, ConditionResult, ConditionTimestamp, failed)
+        let cases = [
+            ("never started", "no", "", false),
+            ("never started, older systemd", "no", "n/a", false),
+            (
+                "condition failed",
+                "no",
+                "Thu 2024-05-02 10:00:00 UTC",
+                true,
+            ),
+            ("condition met", "yes", "Thu 2024-05-02 10:00:00 UTC", false),
+        ];
+
+        for (what, result, timestamp, failed) in cases {
+            assert_eq!(check_failed(result, timestamp), failed, "{what}");
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+use crate::config::Action;
+use crate::helper::spawn_blocking_limited;
+use crate::request_id::RequestId;
+use crate::routes::{ALLOWED_SIGNALS, CurrentRole, Target, check_action, run_action};
+
+/// How long a finished job can still be looked up
+const JOB_TTL: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    id: u64,
+    service: String,
+    action: Action,
+    status: JobStatus,
+    /// How the action ended when there is more to it than success, e.g. `force-killed after
+    /// timeout` for a stop
+    outcome: Option<String>,
+    error: Option<String>,
+    #[serde(skip)]
+    finished: Option<Instant>,
+}
+
+/// Actions running in the background, kept until `JOB_TTL` after they finish
+#[derive(Default)]
+pub struct Jobs {
+    next_id: AtomicU64,
+    jobs: Mutex<HashMap<u64, Job>>,
+}
+
+impl Jobs {
+    fn start(&self, service: &str, action: Action) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|_, job| job.finished.is_none_or(|at| at.elapsed() < JOB_TTL));
+        jobs.insert(
+            id,
+            Job {
+                id,
+                service: service.to_owned(),
+                action,
+                status: JobStatus::Running,
+                outcome: None,
+                error: None,
+                finished: None,
+            },
+        );
+
+        id
+    }
+
+    fn finish(&self, id: u64, result: Result<Option<String>, String>) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            job.status = match result {
+                Ok(_) => JobStatus::Succeeded,
+                Err(_) => JobStatus::Failed,
+            };
+            (job.outcome, job.error) = match result {
+                Ok(outcome) => (outcome, None),
+                Err(error) => (None, Some(error)),
+            };
+            job.finished = Some(Instant::now());
+        }
+    }
+
+    fn get(&self, id: u64) -> Option<Job> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(&id)
+            .filter(|job| job.finished.is_none_or(|at| at.elapsed() < JOB_TTL))
+            .cloned()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ActionQuery {
+    action: Action,
+    signal: Option<String>,
+    #[serde(default)]
+    target: Target,
+}
+
+#[derive(Serialize)]
+pub struct JobCreated {
+    pub job_id: u64,
+}
+
+/// Runs an action checked by `check_action` as a job and waits for it up to `wait`. Gives back the
+/// id of the job when it is still running by then, e.g. a stop waiting for a slow unit
+pub fn run_action_waiting(
+    state: &AppState,
+    action: Action,
+    args: Vec<String>,
+    unit: String,
+    request_id: String,
+    wait: Duration,
+) -> Result<anyhow::Result<Option<String>>, u64> {
+    let job_id = state.jobs.start(&unit, action);
+
+    let (sender, receiver) = mpsc::channel();
+    let state = state.clone();
+
+    thread::spawn(move || {
+        let result = run_action(&state, action, &args, &unit, &request_id);
+        state.jobs.finish(
+            job_id,
+            result.as_ref().map_err(|e| format!("{e:#}")).cloned(),
+        );
+        // Nobody is waiting anymore when the action took too long
+        let _ = sender.send(result);
+    });
+
+    receiver.recv_timeout(wait).map_err(|_| job_id)
+}
+
+/// Checks the action like the synchronous endpoints, then runs it in the background and answers
+/// `202 Accepted` with the id to poll on `/jobs/{job_id}`
+pub async fn handle_action_job(
+    Path(service): Path<String>,
+    CurrentRole(role): CurrentRole,
+    RequestId(request_id): RequestId,
+    Query(query): Query<ActionQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let args = match (query.action, query.signal) {
+        (Action::Kill, Some(signal)) if ALLOWED_SIGNALS.contains(&signal.as_str()) => {
+            vec![format!("--signal={signal}")]
+        }
+        (Action::Kill, _) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("signal must be one of {}", ALLOWED_SIGNALS.join(", ")),
+            )
+                .into_response();
+        }
+        (_, _) => Vec::new(),
+    };
+
+    let action = query.action;
+    let target = query.target;
+
+    let checked = {
+        let state = state.clone();
+        spawn_blocking_limited(move || check_action(&state, &service, target, action, role)).await
+    };
+
+    let unit = match checked {
+        Ok(Ok((_, unit))) => unit,
+        Ok(Err(refusal)) => return refusal.into_response(),
+        Err(e) => {
+            error!("Blocking task failed: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+        }
+    };
+
+    let job_id = state.jobs.start(&unit, action);
+
+    tokio::spawn(spawn_blocking_limited(move || {
+        let result = run_action(&state, action, &args, &unit, &request_id).map_err(|e| {
+            error!("{e}");
+            format!("{e:#}")
+        });
+        state.jobs.finish(job_id, result);
+    }));
+
+    (StatusCode::ACCEPTED, Json(JobCreated { job_id })).into_response()
+}
+
+pub async fn handle_job(Path(job_id): Path<u64>, State(state): State<AppState>) -> Response {
+    match state.jobs.get(job_id) {
+        Some(job) => Json(job).into_response(),
+        None => (StatusCode::NOT_FOUND, "Job not found").into_response(),
+    }
+}
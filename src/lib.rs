@@ -0,0 +1,558 @@
+//! The dashboard as a library, to mount it in another axum server:
+//!
+//! ```ignore
+//! let config = daemon_manager::load_and_validate(&source)?;
+//! let state = daemon_manager::build_state(config, source)?;
+//! daemon_manager::start_background_tasks(&state);
+//!
+//! let app = axum::Router::new().nest("/daemons/api", daemon_manager::build_router(state));
+//! ```
+
+mod auth;
+mod breaker;
+mod config;
+mod helper;
+mod jobs;
+mod metrics;
+mod poller;
+mod request_id;
+mod routes;
+mod stream;
+mod templates;
+mod watcher;
+
+use routes::{
+    handle_add_service, handle_all_units, handle_analyze, handle_config, handle_custom_action,
+    handle_dependencies, handle_favicon, handle_health, handle_host_services, handle_kill,
+    handle_logs, handle_logs_html, handle_logs_since_cursor, handle_maintenance, handle_manifest,
+    handle_mask, handle_problems, handle_reload, handle_remove_service, handle_restart,
+    handle_service, handle_service_names, handle_service_status, handle_services, handle_stop,
+    handle_summary, handle_tags, handle_unmask, handle_wall, no_cache,
+};
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock, atomic::AtomicBool},
+    time::Duration,
+};
+
+use anyhow::Result;
+use axum::{
+    Router, middleware,
+    routing::{delete, get, post},
+};
+
+use auth::{AuthLimiter, auth_guard};
+use breaker::Breakers;
+use config::ServiceConfig;
+use helper::{ProcessNode, SocketInfo, check_units};
+use jobs::{Jobs, handle_action_job, handle_job};
+use log::warn;
+use metrics::{Metrics, handle_metrics, track_requests};
+use poller::{Budgets, History};
+use serde::{Deserialize, Serialize};
+use stream::{LogStreams, handle_logs_stream, handle_merged_logs_stream};
+
+pub use config::{Config, ConfigSource, generate_config, load_and_validate};
+pub use helper::{build_systemctl, check_unit, init_remote_host, set_subprocess_limit};
+pub use templates::{builtin_templates, template_env};
+
+#[derive(Clone)]
+pub struct AppState {
+    /// Replaced as a whole by `/admin/reload`. Use `AppState::config` to get the current one
+    config: Arc<RwLock<Arc<Config>>>,
+    config_source: Arc<ConfigSource>,
+    template_env: Arc<minijinja::Environment<'static>>,
+    /// Toggled at runtime through `/admin/maintenance`. Blocks every action while set
+    maintenance: Arc<AtomicBool>,
+    /// Actions started through `/service/{service}/action`
+    jobs: Arc<Jobs>,
+    /// Last state of each service seen by the poller, by unit name
+    statuses: Arc<RwLock<HashMap<String, ServiceInfo>>>,
+    /// Whether each service was active at the last polls, by the same key as `statuses`
+    history: Arc<History>,
+    /// Services over their memory or CPU budget, by the same key as `statuses`
+    budgets: Arc<Budgets>,
+    /// Services that keep failing to be queried
+    breakers: Arc<Breakers>,
+    /// Requests served, for `/metrics`
+    metrics: Arc<Metrics>,
+    /// `systemctl is-system-running` at the last poll. `None` until read or with
+    /// `check_system_state` off
+    system_state: Arc<RwLock<Option<String>>>,
+    auth_limiter: Arc<AuthLimiter>,
+    /// Journal followers of the live log streams
+    log_streams: Arc<LogStreams>,
+}
+
+impl AppState {
+    /// State with maintenance off and no jobs. `templates::builtin_templates` gives an
+    /// environment that does not depend on the working directory
+    pub fn new(
+        config: Arc<Config>,
+        config_source: ConfigSource,
+        template_env: minijinja::Environment<'static>,
+    ) -> Self {
+        AppState {
+            config: Arc::new(RwLock::new(config)),
+            config_source: Arc::new(config_source),
+            template_env: Arc::new(template_env),
+            maintenance: Arc::new(AtomicBool::new(false)),
+            jobs: Arc::new(Jobs::default()),
+            statuses: Arc::new(RwLock::new(HashMap::new())),
+            history: Arc::new(History::default()),
+            budgets: Arc::new(Budgets::default()),
+            breakers: Arc::new(Breakers::default()),
+            metrics: Arc::new(Metrics::default()),
+            system_state: Arc::new(RwLock::new(None)),
+            auth_limiter: Arc::new(AuthLimiter::default()),
+            log_streams: Arc::new(LogStreams::default()),
+        }
+    }
+
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+}
+
+/// Sets up the subprocess limits and checks that the configured units exist, like the binary
+/// does before it starts listening
+pub fn build_state(config: Config, config_source: ConfigSource) -> Result<AppState> {
+    set_subprocess_limit(
+        config.max_subprocesses,
+        Duration::from_secs(config.subprocess_timeout_secs),
+        config.subprocess_retries,
+    );
+
+    if let Some(host) = config.remote_host() {
+        init_remote_host(host);
+    }
+
+    check_units(&build_systemctl(&config, None), &config)?;
+
+    Ok(AppState::new(
+        Arc::new(config),
+        config_source,
+        template_env(),
+    ))
+}
+
+/// All the endpoints, without the `/api` prefix that Caddy strips
+pub fn build_router(state: AppState) -> Router {
+    Router::new()
+        .route("/services", get(handle_services).post(handle_add_service))
+        .route("/services/names", get(handle_service_names))
+        .route("/services/{service}", delete(handle_remove_service))
+        .route("/problems", get(handle_problems))
+        .route("/tags", get(handle_tags))
+        .route("/summary", get(handle_summary))
+        .route("/wall", get(handle_wall))
+        .route("/health", get(handle_health))
+        .route("/metrics", get(handle_metrics))
+        .route("/host/{host}/services", get(handle_host_services))
+        .route("/service/{service}", get(handle_service))
+        .route("/service/{service}/logs", get(handle_logs))
+        .route("/service/{service}/logs/html", get(handle_logs_html))
+        .route(
+            "/service/{service}/logs/since-cursor",
+            get(handle_logs_since_cursor),
+        )
+        .route("/service/{service}/logs/stream", get(handle_logs_stream))
+        .route("/service/{service}/analyze", get(handle_analyze))
+        .route("/service/{service}/dependencies", get(handle_dependencies))
+        .route("/service/{service}/status", get(handle_service_status))
+        .route("/service/{service}/mask", post(handle_mask))
+        .route("/service/{service}/unmask", post(handle_unmask))
+        .route("/service/{service}/kill", post(handle_kill))
+        .route("/service/{service}/stop", post(handle_stop))
+        .route("/service/{service}/restart", post(handle_restart))
+        .route("/service/{service}/action", post(handle_action_job))
+        .route(
+            "/service/{service}/action/{name}",
+            post(handle_custom_action),
+        )
+        .route("/logs/stream", get(handle_merged_logs_stream))
+        .route("/jobs/{job_id}", get(handle_job))
+        .route("/admin/maintenance", post(handle_maintenance))
+        .route("/admin/reload", post(handle_reload))
+        .route("/config", get(handle_config))
+        .route("/all-units", get(handle_all_units))
+        .layer(middleware::map_response(no_cache))
+        // Added after `no_cache` so it is not applied to them: they only change with the config
+        .route("/manifest.webmanifest", get(handle_manifest))
+        .route("/favicon", get(handle_favicon))
+        .layer(middleware::from_fn_with_state(state.clone(), auth_guard))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            track_requests,
+        ))
+        .layer(middleware::from_fn(request_id::request_id))
+        .with_state(state)
+}
+
+/// Starts the poller and, with `watch_config`, the config watcher. Must be called from within
+/// a tokio runtime
+pub fn start_background_tasks(state: &AppState) {
+    if state.config().watch_config {
+        match &*state.config_source {
+            ConfigSource::File(path) => {
+                let state = state.clone();
+                let path = path.clone();
+                std::thread::spawn(move || watcher::watch_config(state, path));
+            }
+            source => warn!("watch_config has no effect when the config is read from {source}"),
+        }
+    }
+
+    tokio::spawn(poller::poll(state.clone()));
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ServiceInfo {
+    config: ServiceConfig,
+    status: String,
+    active: bool,
+    enabled: bool,
+    running: bool,
+    /// Active but without processes, like a oneshot unit that finished
+    exited: bool,
+    pid: Option<u64>,
+    status_code: Option<u8>,
+    uptime: String,
+    /// How long the unit has been inactive or failed
+    down_for: String,
+    /// When the main process was started
+    started: String,
+    /// When the unit became active, which can be earlier than `started`
+    active_since: String,
+    failed: bool,
+    restarts: u32,
+    description: String,
+    /// The unit could not be queried, e.g. because `systemctl` timed out
+    unavailable: bool,
+    /// systemd does not know the unit, e.g. it was uninstalled. Shown as such rather than as a
+    /// problem
+    not_found: bool,
+    /// Name of the `[[host]]` entry the service runs on
+    host: Option<String>,
+    /// Percentage of the polls over `availability_window_days` that found the service active.
+    /// `None` until enough polls were made
+    availability: Option<f64>,
+    /// State of the configured `socket` unit
+    socket: Option<SocketInfo>,
+    /// Why the service is over its `memory_alert_bytes` or `cpu_alert_percent`, e.g.
+    /// `memory 1.2 GiB > 1.0 GiB`
+    over_budget: Option<String>,
+}
+
+/// Restarts after which a service is considered to be flapping
+const FLAPPING_RESTARTS: u32 = 3;
+
+impl ServiceInfo {
+    /// Placeholder card for a service whose state could not be read
+    pub fn unavailable(config: ServiceConfig) -> Self {
+        ServiceInfo {
+            config,
+            status: "unknown".to_owned(),
+            active: false,
+            enabled: false,
+            running: false,
+            exited: false,
+            pid: None,
+            status_code: None,
+            uptime: String::new(),
+            down_for: String::new(),
+            started: String::new(),
+            active_since: String::new(),
+            failed: false,
+            restarts: 0,
+            description: String::new(),
+            unavailable: true,
+            not_found: false,
+            host: None,
+            availability: None,
+            socket: None,
+            over_budget: None,
+        }
+    }
+
+    /// Card of a service whose unit systemd does not know
+    pub fn not_found(config: ServiceConfig) -> Self {
+        ServiceInfo {
+            status: "not-found".to_owned(),
+            unavailable: false,
+            not_found: true,
+            ..ServiceInfo::unavailable(config)
+        }
+    }
+
+    /// Whether the service needs attention: failed, unavailable, enabled but not active, exited
+    /// with an error or restarting repeatedly
+    pub fn is_problem(&self) -> bool {
+        self.failed
+            || self.unavailable
+            || (self.enabled && !self.active)
+            || (!self.running && self.status_code.is_some_and(|c| c != 0))
+            || self.restarts >= FLAPPING_RESTARTS
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ServiceDetail {
+    config: ServiceConfig,
+    status: String,
+    active: bool,
+    enabled: bool,
+    running: bool,
+    pid: Option<u64>,
+    status_code: Option<u8>,
+    uptime: String,
+    active_since: String,
+    r#type: String,
+    /// `Restart=` policy, e.g. `on-failure`
+    restart: String,
+    /// `Type` and `restart` in plain words
+    behavior: Vec<String>,
+    unit_file: String,
+    /// Created at runtime, e.g. by `systemd-run`, so there is no unit file to edit
+    transient: bool,
+    /// Written by a systemd generator, editing `unit_file` has no lasting effect
+    generated: bool,
+    /// File the generator built the unit from, e.g. `/etc/fstab`
+    source_path: String,
+    /// `Environment=` variables, with the values of secrets hidden
+    environment: Vec<(String, String)>,
+    environment_files: String,
+    working_directory: String,
+    /// Memory used by the unit, e.g. `12.3 MiB`. Empty without memory accounting
+    memory: String,
+    /// Units listed in `Requires=` and `Wants=`
+    dependencies: Vec<String>,
+    /// Why the unit is inactive when a `Condition*=` or `Assert*=` kept it from starting
+    skipped: Option<String>,
+    /// Processes of the unit grouped by cgroup
+    processes: Vec<ProcessNode>,
+    configuration: String,
+    /// Values of the configured `extra_properties`
+    properties: Vec<(String, String)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::{Body, to_bytes},
+        extract::connect_info::MockConnectInfo,
+        http::{Request, StatusCode, header},
+        response::Response,
+    };
+    use std::{net::SocketAddr, sync::atomic::Ordering};
+    use tower::ServiceExt;
+
+    fn state(config: &str) -> AppState {
+        AppState::new(
+            Arc::new(toml::from_str(config).unwrap()),
+            ConfigSource::Env(String::new()),
+            templates::builtin_templates(),
+        )
+    }
+
+    fn router(state: AppState) -> Router {
+        build_router(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))))
+    }
+
+    async fn get(router: Router, uri: &str) -> Response {
+        get_with_token(router, uri, None).await
+    }
+
+    async fn get_with_token(router: Router, uri: &str, token: Option<&str>) -> Response {
+        let mut request = Request::get(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+
+        router
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn post_with_token(router: Router, uri: &str, token: Option<&str>) -> Response {
+        let mut request = Request::post(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+
+        router
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body_text(response: Response) -> String {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn cards_render_from_the_builtin_templates() {
+        let response = get(router(state("")), "/problems").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let page = body_text(response).await;
+        assert!(page.contains("All services are healthy"), "{page}");
+        assert!(!page.contains("maintenance-banner"), "{page}");
+    }
+
+    #[tokio::test]
+    async fn cards_show_the_maintenance_banner() {
+        let state = state("");
+        state.maintenance.store(true, Ordering::Relaxed);
+
+        let page = body_text(get(router(state), "/problems").await).await;
+
+        assert!(page.contains("maintenance-banner"), "{page}");
+    }
+
+    #[tokio::test]
+    async fn cards_answer_304_only_while_the_page_is_the_same() {
+        let state = state("");
+
+        let first = get(router(state.clone()), "/problems").await;
+        let etag = first.headers()[header::ETAG].clone();
+
+        let request = || {
+            Request::get("/problems")
+                .header(header::IF_NONE_MATCH, etag.clone())
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let same = router(state.clone()).oneshot(request()).await.unwrap();
+        assert_eq!(same.status(), StatusCode::NOT_MODIFIED);
+
+        // The banner is part of the page, so the cached copy is stale
+        state.maintenance.store(true, Ordering::Relaxed);
+        let changed = router(state).oneshot(request()).await.unwrap();
+        assert_eq!(changed.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn only_dynamic_responses_are_not_cached() {
+        let services = get(router(state("")), "/services").await;
+        assert_eq!(
+            services.headers().get(header::CACHE_CONTROL).unwrap(),
+            "no-cache, must-revalidate"
+        );
+
+        for uri in ["/favicon", "/manifest.webmanifest"] {
+            let response = get(router(state("")), uri).await;
+            assert!(response.status().is_success(), "{uri}");
+            assert!(
+                !response.headers().contains_key(header::CACHE_CONTROL),
+                "{uri}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn welcome_page_is_rendered_from_the_builtin_templates() {
+        let response = get(router(state("")), "/services").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let page = body_text(response).await;
+        assert!(page.contains("<h2>No services configured</h2>"), "{page}");
+    }
+
+    #[tokio::test]
+    async fn config_is_only_shown_to_admins_and_redacted() {
+        let config = r#"
+            admin_token = "admin-s3cret"
+
+            [[user]]
+            name = "alice"
+            token = "user-s3cret"
+            role = "operator"
+        "#;
+
+        let anonymous = get(router(state(config)), "/config").await;
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+
+        let operator = get_with_token(router(state(config)), "/config", Some("user-s3cret")).await;
+        assert_eq!(operator.status(), StatusCode::UNAUTHORIZED);
+
+        let admin = get_with_token(router(state(config)), "/config", Some("admin-s3cret")).await;
+        assert_eq!(admin.status(), StatusCode::OK);
+
+        let shown = body_text(admin).await;
+        assert!(shown.contains("alice"), "{shown}");
+        assert!(!shown.contains("s3cret"), "{shown}");
+    }
+
+    #[tokio::test]
+    async fn custom_actions_are_only_run_when_allowed() {
+        let config = r#"
+            [[user]]
+            name = "alice"
+            token = "operator-token"
+            role = "operator"
+
+            [[user]]
+            name = "bob"
+            token = "viewer-token"
+            role = "viewer"
+
+            [[custom_action]]
+            name = "reload-certs"
+            command = ["/bin/true"]
+
+            [[custom_action]]
+            name = "rotate-logs"
+            command = ["/bin/true"]
+
+            [[service]]
+            service_name = "demo.service"
+            friendly_name = "Demo"
+            custom_actions = ["reload-certs"]
+        "#;
+
+        // (what, uri, token, status)
+        let cases = [
+            (
+                "viewer",
+                "/service/demo.service/action/reload-certs",
+                Some("viewer-token"),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "anonymous",
+                "/service/demo.service/action/reload-certs",
+                None,
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "not listed by the service",
+                "/service/demo.service/action/rotate-logs",
+                Some("operator-token"),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "unknown action",
+                "/service/demo.service/action/reboot",
+                Some("operator-token"),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                "unknown service",
+                "/service/other.service/action/reload-certs",
+                Some("operator-token"),
+                StatusCode::NOT_FOUND,
+            ),
+        ];
+
+        for (what, uri, token, status) in cases {
+            let response = post_with_token(router(state(config)), uri, token).await;
+            assert_eq!(response.status(), status, "{what}");
+        }
+    }
+}
@@ -1,180 +1,159 @@
-mod helper;
-mod routes;
-
-use minijinja::Environment;
-use routes::{handle_service, handle_services};
-
 use std::{
-    env::var,
-    net::{Ipv4Addr, SocketAddrV4},
+    env::{VarError, var},
+    net::{Ipv4Addr, SocketAddr},
     path::PathBuf,
     str::FromStr,
-    sync::Arc,
+    time::Duration,
 };
 
-use axum::{Router, routing::get};
-
+use daemon_manager::{
+    ConfigSource, build_router, build_state, build_systemctl, check_unit, generate_config,
+    init_remote_host, load_and_validate, set_subprocess_limit, start_background_tasks,
+};
 use log::{error, info, warn};
-use serde::{Deserialize, Serialize};
-use systemctl::{SystemCtl, Unit};
-
-#[derive(Clone)]
-struct AppState {
-    config: Arc<Config>,
-    systemctl: SystemCtl,
-    template_env: Arc<minijinja::Environment<'static>>,
-}
-
-#[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
-pub struct Config {
-    pub service: Vec<ServiceConfig>,
-}
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-#[serde(deny_unknown_fields)]
-pub struct ServiceConfig {
-    pub service_name: String,
-    pub friendly_name: String,
-
-    #[serde(default)]
-    pub show_logs: bool,
-}
-
-#[tokio::main]
-async fn main() {
+fn main() {
     env_logger::builder().format_timestamp(None).init();
 
-    let config_path: String = var("DAEMON_MANAGER_CONFIG_PATH")
-        .map_err(|e| {
-            warn!("DAEMON_MANAGER_CONFIG_PATH is not set: {e}. Will use default services.toml")
-        })
-        .unwrap_or("services.toml".into());
+    if std::env::args().any(|arg| arg == "--generate-config") {
+        match generate_config() {
+            Ok(config) => {
+                print!("{config}");
+                std::process::exit(0);
+            }
+            Err(e) => {
+                error!("Could not generate config: {e:#}");
+                std::process::exit(1);
+            }
+        }
+    }
 
-    let config_path: PathBuf = PathBuf::from_str(&config_path).unwrap(); // Infallible
+    let args: Vec<String> = std::env::args().collect();
 
-    let config_str = std::fs::read_to_string(config_path.clone())
-        .map_err(|e| {
-            error!(
-                "Could not read configuration file '{}': {e}",
-                config_path.display()
-            );
-            std::process::exit(1);
-        })
-        .unwrap();
+    let config_arg = match args.iter().position(|arg| arg == "--config") {
+        Some(i) => match args.get(i + 1) {
+            Some(value) => Some(value.clone()),
+            None => {
+                error!("--config needs a path, or - to read from stdin");
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // --config, then the inline DAEMON_MANAGER_CONFIG, then the DAEMON_MANAGER_CONFIG_PATH file
+    let config_source = match (config_arg, var("DAEMON_MANAGER_CONFIG")) {
+        (Some(arg), _) if arg == "-" => ConfigSource::Stdin,
+        (Some(arg), _) => ConfigSource::File(PathBuf::from(arg)),
+        (None, Ok(config)) => ConfigSource::Env(config),
+        (None, Err(_)) => {
+            let config_path: String = var("DAEMON_MANAGER_CONFIG_PATH")
+                .map_err(|e| {
+                    warn!(
+                        "DAEMON_MANAGER_CONFIG_PATH is not set: {e}. Will use default services.toml"
+                    )
+                })
+                .unwrap_or("services.toml".into());
+
+            ConfigSource::File(PathBuf::from_str(&config_path).unwrap()) // Infallible
+        }
+    };
 
-    let config: Config = toml::from_str(&config_str)
+    let config = load_and_validate(&config_source)
         .map_err(|e| {
-            error!("Configuration error: {e}");
+            error!("Configuration error in {config_source}: {e:#}");
             std::process::exit(1)
         })
         .unwrap();
 
-    let incorrect = config
-        .service
-        .iter()
-        .map(|v| match v.service_name.rsplit_once('.') {
-            Some((_, _)) => true,
-            None => {
-                error!("Invalid service name: {}", v.service_name);
-                false
+    if args.iter().any(|arg| arg == "--print-effective-config") {
+        match config.redacted_toml() {
+            Ok(config) => {
+                print!("{config}");
+                std::process::exit(0);
             }
-        })
-        .any(|b| !b);
-
-    if incorrect {
-        std::process::exit(1);
-    }
-
-    // TODO: Make this for Non-Nix systems
-    let systemctl = SystemCtl::builder()
-        .path("/run/current-system/sw/bin/systemctl".into())
-        .additional_args(Vec::new())
-        .build();
-
-    let units = config
-        .service
-        .iter()
-        .filter_map(|s| match systemctl.create_unit(&s.service_name) {
-            Ok(unit) => Some(unit),
             Err(e) => {
-                error!("Failed to create unit for {}: {}", &s.service_name, e);
-                None
+                error!("{e:#}");
+                std::process::exit(1);
             }
-        })
-        .collect::<Vec<Unit>>();
-
-    let invalid_units = units.iter().any(|unit| match unit.state {
-        systemctl::State::Loaded => false,
-        systemctl::State::Masked => {
-            error!("Unit {} is not loaded (masked or not found)", unit.name);
-            true
         }
-    });
-
-    if invalid_units {
-        error!("Erroneous services found. Exiting");
-        std::process::exit(1);
     }
 
-    let mut env = Environment::new();
+    if let Some(i) = args.iter().position(|arg| arg == "--check-unit") {
+        let Some(name) = args.get(i + 1) else {
+            error!("--check-unit needs a unit name");
+            std::process::exit(1);
+        };
 
-    env.set_loader(minijinja::path_loader("./templates"));
+        set_subprocess_limit(
+            config.max_subprocesses,
+            Duration::from_secs(config.subprocess_timeout_secs),
+            config.subprocess_retries,
+        );
 
-    let env = Arc::new(env);
+        if let Some(host) = config.remote_host() {
+            init_remote_host(host);
+        }
 
-    let config = Arc::new(config);
+        let systemctl = build_systemctl(&config, None);
 
-    let state = AppState {
-        config: config.clone(),
-        systemctl: systemctl.clone(),
-        template_env: env,
+        print!("{}", check_unit(&systemctl, &config, name));
+        std::process::exit(0);
+    }
+
+    let state = match build_state(config, config_source) {
+        Ok(state) => state,
+        Err(e) => {
+            error!("{e}. Exiting");
+            std::process::exit(1);
+        }
     };
 
-    let addr: String = var("DAEMON_MANAGER_ADDR")
-        .map_err(|e| warn!("DAEMON_MANAGER_ADDR is not set: {e}. Will use default 127.0.0.1:3000"))
-        .unwrap_or("127.0.0.1:3000".into());
+    let config = state.config();
 
-    let addr: SocketAddrV4 = SocketAddrV4::from_str(&addr)
-        .map_err(|e| error!("Could not parse IP addr {addr}: {e}. Will use default 127.0.0.1:3000"))
-        .unwrap_or(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 3000));
+    let default_addr = SocketAddr::from((Ipv4Addr::new(127, 0, 0, 1), 3000));
 
-    let app = Router::new()
-        .route("/services", get(handle_services))
-        .route("/service/{service}", get(handle_service))
-        .with_state(state);
+    let addr: SocketAddr = match (var("DAEMON_MANAGER_ADDR"), config.server.listen) {
+        // A typo must not leave the manager listening somewhere else
+        (Ok(addr), _) => match SocketAddr::from_str(addr.trim()) {
+            Ok(addr) => addr,
+            Err(e) => {
+                error!("Invalid DAEMON_MANAGER_ADDR {addr:?}: {e}. Expected e.g. 0.0.0.0:3000");
+                std::process::exit(1);
+            }
+        },
+        (Err(VarError::NotUnicode(addr)), _) => {
+            error!("Invalid DAEMON_MANAGER_ADDR {addr:?}: not valid UTF-8");
+            std::process::exit(1);
+        }
+        (Err(VarError::NotPresent), Some(listen)) => listen,
+        (Err(VarError::NotPresent), None) => {
+            warn!("DAEMON_MANAGER_ADDR is not set. Will use default {default_addr}");
+            default_addr
+        }
+    };
 
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    let app = build_router(state.clone());
 
-    info!("Listening on {addr}");
+    // Built by hand rather than with #[tokio::main] so the blocking pool follows the config
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .max_blocking_threads(config.blocking_threads)
+        .build()
+        .unwrap();
 
-    axum::serve(listener, app).await.unwrap();
-}
+    runtime.block_on(async {
+        start_background_tasks(&state);
 
-#[derive(Deserialize, Serialize)]
-pub struct ServiceInfo {
-    config: ServiceConfig,
-    status: String,
-    active: bool,
-    enabled: bool,
-    running: bool,
-    pid: Option<u64>,
-    status_code: Option<u8>,
-    uptime: String,
-}
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
 
-#[derive(Deserialize, Serialize)]
-pub struct ServiceDetail {
-    config: ServiceConfig,
-    status: String,
-    active: bool,
-    enabled: bool,
-    running: bool,
-    pid: Option<u64>,
-    status_code: Option<u8>,
-    uptime: String,
-    r#type: String,
-    unit_file: String,
-    processes: Vec<u32>,
-    configuration: String,
+        info!("Listening on {addr}");
+
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .unwrap();
+    });
 }
@@ -0,0 +1,137 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use axum::{
+    extract::{Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System};
+
+use crate::AppState;
+use crate::helper::SUBPROCESSES_SPAWNED;
+
+/// Upper bounds of the request duration buckets, in seconds
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Health of the manager itself, as opposed to that of the services
+#[derive(Default)]
+pub struct Metrics {
+    requests: AtomicU64,
+    /// Requests answered with 304 because the client already had the cards, the only cache
+    not_modified: AtomicU64,
+    /// Requests that took at most each of `BUCKETS`
+    buckets: [AtomicU64; BUCKETS.len()],
+    duration_us: AtomicU64,
+}
+
+impl Metrics {
+    fn record(&self, status: StatusCode, elapsed_secs: f64) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+
+        if status == StatusCode::NOT_MODIFIED {
+            self.not_modified.fetch_add(1, Ordering::Relaxed);
+        }
+
+        for (bound, count) in BUCKETS.iter().zip(&self.buckets) {
+            if elapsed_secs <= *bound {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        self.duration_us
+            .fetch_add((elapsed_secs * 1e6) as u64, Ordering::Relaxed);
+    }
+}
+
+/// Counts every request and how long it took
+pub async fn track_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let response = next.run(request).await;
+
+    state
+        .metrics
+        .record(response.status(), start.elapsed().as_secs_f64());
+
+    response
+}
+
+/// Resident memory of this process, in bytes
+fn resident_memory() -> Option<u64> {
+    let pid = sysinfo::get_current_pid().ok()?;
+    let mut sys = System::new();
+    sys.refresh_processes_specifics(
+        ProcessesToUpdate::Some(&[pid]),
+        true,
+        ProcessRefreshKind::nothing().with_memory(),
+    );
+    sys.process(pid).map(|process| process.memory())
+}
+
+/// The manager's own metrics in the Prometheus text format
+pub async fn handle_metrics(State(state): State<AppState>) -> Response {
+    let metrics = &state.metrics;
+    let mut body = String::new();
+
+    // Writing to a String cannot fail
+    if let Some(memory) = resident_memory() {
+        let _ = writeln!(
+            body,
+            "# HELP daemon_manager_resident_memory_bytes Resident memory of the manager\n\
+             # TYPE daemon_manager_resident_memory_bytes gauge\n\
+             daemon_manager_resident_memory_bytes {memory}"
+        );
+    }
+
+    let _ = writeln!(
+        body,
+        "# HELP daemon_manager_subprocesses_spawned_total Commands run, including \
+         retries and log followers\n\
+         # TYPE daemon_manager_subprocesses_spawned_total counter\n\
+         daemon_manager_subprocesses_spawned_total {}",
+        SUBPROCESSES_SPAWNED.load(Ordering::Relaxed)
+    );
+
+    let _ = writeln!(
+        body,
+        "# HELP daemon_manager_not_modified_total Requests answered from the client's cache \
+         with 304\n\
+         # TYPE daemon_manager_not_modified_total counter\n\
+         daemon_manager_not_modified_total {}",
+        metrics.not_modified.load(Ordering::Relaxed)
+    );
+
+    let _ = writeln!(
+        body,
+        "# HELP daemon_manager_request_duration_seconds Time taken to answer requests\n\
+         # TYPE daemon_manager_request_duration_seconds histogram"
+    );
+
+    for (bound, count) in BUCKETS.iter().zip(&metrics.buckets) {
+        let _ = writeln!(
+            body,
+            "daemon_manager_request_duration_seconds_bucket{{le=\"{bound}\"}} {}",
+            count.load(Ordering::Relaxed)
+        );
+    }
+
+    let requests = metrics.requests.load(Ordering::Relaxed);
+
+    let _ = writeln!(
+        body,
+        "daemon_manager_request_duration_seconds_bucket{{le=\"+Inf\"}} {requests}\n\
+         daemon_manager_request_duration_seconds_sum {}\n\
+         daemon_manager_request_duration_seconds_count {requests}",
+        metrics.duration_us.load(Ordering::Relaxed) as f64 / 1e6
+    );
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response()
+}
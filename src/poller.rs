@@ -0,0 +1,322 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use futures_util::{StreamExt, stream};
+use log::{debug, error, info, warn};
+
+use crate::breaker::Tripped;
+use crate::config::{Config, HostConfig, ServiceConfig};
+use crate::helper::{
+    ResourceUsage, build_systemctl, format_bytes, get_unit_info, on_host, resource_usage,
+    spawn_blocking_limited, systemctl_is_system_running,
+};
+use crate::{AppState, ServiceInfo};
+
+/// Refreshes the state of every service in the background, at most `poll_concurrency` at a
+/// time, and logs when a service starts or stops needing attention
+pub async fn poll(state: AppState) {
+    loop {
+        // Read on every cycle so a reload changes the services and the interval
+        let config = state.config();
+
+        let system_state = match config.check_system_state {
+            true => spawn_blocking_limited(systemctl_is_system_running)
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|result| result)
+                .map_err(|e| error!("Could not read the state of systemd: {e}"))
+                .ok(),
+            false => None,
+        };
+
+        {
+            let mut previous = state.system_state.write().unwrap();
+
+            match (previous.as_deref(), system_state.as_deref()) {
+                (old, Some(new)) if old != Some(new) && new != "running" => {
+                    warn!("systemd is {new}")
+                }
+                (Some(old), Some("running")) if old != "running" => info!("systemd is running"),
+                _ => {}
+            }
+
+            *previous = system_state;
+        }
+
+        // Every service on every `[[host]]`, or on the managed machine
+        let hosts: Vec<Option<HostConfig>> = match config.hosts() {
+            [] => vec![None],
+            hosts => hosts.iter().cloned().map(Some).collect(),
+        };
+
+        let targets: Vec<(Option<HostConfig>, ServiceConfig)> = hosts
+            .iter()
+            .flat_map(|host| config.service.iter().map(|s| (host.clone(), s.clone())))
+            .collect();
+
+        let polled: Vec<(ServiceInfo, Option<ResourceUsage>)> = stream::iter(targets)
+            .map(|(host, service)| {
+                let state = state.clone();
+                let config = config.clone();
+                async move {
+                    spawn_blocking_limited(move || {
+                        poll_service(&state, host.as_ref(), &service, &config)
+                    })
+                    .await
+                    .map_err(|e| error!("Blocking task failed: {e}"))
+                    .ok()
+                }
+            })
+            .buffer_unordered(config.poll_concurrency)
+            .filter_map(|info| async move { info })
+            .collect()
+            .await;
+
+        let polled: HashMap<String, ServiceInfo> = polled
+            .into_iter()
+            .map(|(info, usage)| {
+                let key = status_key(&info);
+                state
+                    .budgets
+                    .record(&key, &info.config, usage, config.alert_samples);
+                (key, info)
+            })
+            .collect();
+
+        state.budgets.retain(&polled);
+
+        let window =
+            Duration::from_secs(config.availability_window_days.saturating_mul(24 * 60 * 60));
+        state.history.record(&polled, window);
+
+        {
+            let mut statuses = state.statuses.write().unwrap();
+
+            for (name, info) in &polled {
+                let was_problem = statuses.get(name).map(ServiceInfo::is_problem);
+                match (was_problem, info.is_problem()) {
+                    (Some(false) | None, true) => warn!("{name} needs attention"),
+                    (Some(true), false) => info!("{name} recovered"),
+                    _ => {}
+                }
+            }
+
+            *statuses = polled;
+        }
+
+        tokio::time::sleep(Duration::from_secs(config.poll_interval_secs)).await;
+    }
+}
+
+/// Polls needed before an availability is given
+const MIN_AVAILABILITY_SAMPLES: usize = 10;
+
+/// When each service was polled and whether it was active then. Kept in memory only, so it starts
+/// over on restart
+#[derive(Default)]
+pub struct History {
+    samples: RwLock<HashMap<String, VecDeque<(Instant, bool)>>>,
+}
+
+impl History {
+    /// Adds the result of a poll and forgets the samples older than `window`, and the services
+    /// that are no longer polled. A service that could not be queried is not counted either way
+    fn record(&self, polled: &HashMap<String, ServiceInfo>, window: Duration) {
+        let now = Instant::now();
+        let mut samples = self.samples.write().unwrap();
+
+        samples.retain(|key, _| polled.contains_key(key));
+
+        for (key, info) in polled {
+            let history = samples.entry(key.clone()).or_default();
+
+            if !info.unavailable {
+                history.push_back((now, info.active));
+            }
+
+            while history
+                .front()
+                .is_some_and(|(at, _)| now.duration_since(*at) > window)
+            {
+                history.pop_front();
+            }
+        }
+    }
+
+    /// Percentage of the samples of `key` where the service was active, to one decimal
+    pub fn availability(&self, key: &str) -> Option<f64> {
+        let samples = self.samples.read().unwrap();
+        let history = samples.get(key)?;
+
+        if history.len() < MIN_AVAILABILITY_SAMPLES {
+            return None;
+        }
+
+        let active = history.iter().filter(|(_, active)| *active).count();
+        let percentage = active as f64 * 100.0 / history.len() as f64;
+
+        Some((percentage * 10.0).round() / 10.0)
+    }
+}
+
+/// Budget state of a service between two polls
+#[derive(Default)]
+struct Budget {
+    /// CPU time used so far and when it was read, to get the use since the last poll
+    last_cpu: Option<(Instant, u64)>,
+    /// Polls in a row that disagreed with `alert`: over budget while it is unset, under while
+    /// it is set
+    streak: u32,
+    alert: Option<String>,
+}
+
+/// Services over their `memory_alert_bytes` or `cpu_alert_percent`. An alert is only raised, and
+/// only cleared, after `alert_samples` polls in a row, so a short spike does not flap it
+#[derive(Default)]
+pub struct Budgets {
+    budgets: RwLock<HashMap<String, Budget>>,
+}
+
+impl Budgets {
+    /// Compares the `usage` of a poll with the thresholds of `service`. Logs when an alert is
+    /// raised or cleared
+    fn record(
+        &self,
+        key: &str,
+        service: &ServiceConfig,
+        usage: Option<ResourceUsage>,
+        required: u32,
+    ) {
+        let mut budgets = self.budgets.write().unwrap();
+        let budget = budgets.entry(key.to_owned()).or_default();
+
+        let now = Instant::now();
+        let mut over = Vec::new();
+
+        // A stopped service, or one that could not be read, counts as within budget
+        match usage {
+            Some(usage) => {
+                if let Some((limit, memory)) = service
+                    .memory_alert_bytes
+                    .zip(usage.memory_bytes)
+                    .filter(|(limit, memory)| memory > limit)
+                {
+                    over.push(format!(
+                        "memory {} > {}",
+                        format_bytes(memory),
+                        format_bytes(limit)
+                    ));
+                }
+
+                if let Some((limit, cpu)) = service.cpu_alert_percent.zip(usage.cpu_nsec) {
+                    // The counter starts over when the service restarts
+                    if let Some((at, previous)) =
+                        budget.last_cpu.filter(|&(_, previous)| cpu >= previous)
+                    {
+                        let elapsed = now.duration_since(at).as_nanos() as f64;
+                        let percent = (cpu - previous) as f64 * 100.0 / elapsed;
+                        if percent > limit {
+                            over.push(format!("CPU {percent:.0}% > {limit}%"));
+                        }
+                    }
+                    budget.last_cpu = Some((now, cpu));
+                }
+            }
+            None => budget.last_cpu = None,
+        }
+
+        let reason = (!over.is_empty()).then(|| over.join(", "));
+
+        match (&budget.alert, reason) {
+            (None, Some(reason)) => {
+                budget.streak += 1;
+                if budget.streak >= required {
+                    warn!("{key} over budget: {reason}");
+                    budget.alert = Some(reason);
+                    budget.streak = 0;
+                }
+            }
+            (Some(_), None) => {
+                budget.streak += 1;
+                if budget.streak >= required {
+                    info!("{key} back within budget");
+                    budget.alert = None;
+                    budget.streak = 0;
+                }
+            }
+            // Still over, with the latest numbers
+            (Some(_), Some(reason)) => {
+                budget.alert = Some(reason);
+                budget.streak = 0;
+            }
+            (None, None) => budget.streak = 0,
+        }
+    }
+
+    /// Forgets the services that are no longer polled
+    fn retain(&self, polled: &HashMap<String, ServiceInfo>) {
+        self.budgets
+            .write()
+            .unwrap()
+            .retain(|key, _| polled.contains_key(key));
+    }
+
+    pub fn alert(&self, key: &str) -> Option<String> {
+        self.budgets.read().unwrap().get(key)?.alert.clone()
+    }
+}
+
+/// Key of `info` in `AppState::statuses`: the unit name, prefixed by the host if there is one
+pub fn status_key(info: &ServiceInfo) -> String {
+    match &info.host {
+        Some(host) => format!("{host}/{}", info.config.service_name),
+        None => info.config.service_name.clone(),
+    }
+}
+
+fn poll_service(
+    state: &AppState,
+    host: Option<&HostConfig>,
+    service: &ServiceConfig,
+    config: &Config,
+) -> (ServiceInfo, Option<ResourceUsage>) {
+    let has_budget = service.memory_alert_bytes.is_some() || service.cpu_alert_percent.is_some();
+
+    let poll = || {
+        let systemctl = build_systemctl(config, host.map(|host| host.target.as_str()));
+
+        let info = state
+            .breakers
+            .call(&service.service_name, config, || {
+                systemctl
+                    .create_unit(&service.service_name)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|unit| get_unit_info(&unit, config))
+            })
+            .unwrap_or_else(|e| {
+                match e.is::<Tripped>() {
+                    true => debug!("Skipped polling {}: {e}", service.service_name),
+                    false => error!("Error polling {}: {e}", service.service_name),
+                }
+                ServiceInfo::unavailable(service.clone())
+            });
+
+        let usage = match has_budget && info.running {
+            true => resource_usage(&service.service_name)
+                .map_err(|e| error!("Resource usage of {}: {e}", service.service_name))
+                .ok(),
+            false => None,
+        };
+
+        (info, usage)
+    };
+
+    let (mut info, usage) = match host {
+        Some(host) => on_host(&host.target, poll),
+        None => poll(),
+    };
+
+    info.host = host.map(|host| host.name.clone());
+    (info, usage)
+}
@@ -0,0 +1,82 @@
+use std::convert::Infallible;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use axum::{
+    extract::{FromRequestParts, Request},
+    http::{HeaderName, HeaderValue, request::Parts},
+    middleware::Next,
+    response::Response,
+};
+use log::info;
+
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest incoming id that is reused rather than replaced
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Id of the request, from its `X-Request-Id` or generated, to find it in the logs
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+impl FromRequestParts<crate::AppState> for RequestId {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &crate::AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_else(|| RequestId(generate())))
+    }
+}
+
+/// 16 hex digits, different on every call
+fn generate() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    static SEED: OnceLock<RandomState> = OnceLock::new();
+
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:016x}", SEED.get_or_init(RandomState::new).hash_one(n))
+}
+
+/// Keeps the `X-Request-Id` of the client, as long as it is short and printable, or makes one up.
+/// It is sent back on the response and logged with the outcome of the request
+pub async fn request_id(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id.chars().all(|c| c.is_ascii_graphic())
+        })
+        .map(str::to_owned)
+        .unwrap_or_else(generate);
+
+    request.extensions_mut().insert(RequestId(id.clone()));
+
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+    let start = Instant::now();
+
+    let mut response = next.run(request).await;
+
+    info!(
+        "{method} {uri} {} {}ms request_id={id}",
+        response.status().as_u16(),
+        start.elapsed().as_millis()
+    );
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(X_REQUEST_ID.clone(), value);
+    }
+
+    response
+}
@@ -2,115 +2,2711 @@ use crate::helper::*;
 
 use axum::{
     extract::Path,
-    extract::State,
-    http::StatusCode,
-    response::{Html, IntoResponse, Response},
+    extract::{FromRequestParts, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header, request::Parts},
+    response::{Html, IntoResponse, Json, Response},
 };
 
+use std::borrow::Cow;
+use std::convert::Infallible;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
 use anyhow::Context;
-use log::error;
+use log::{debug, error, info};
 use minijinja::context;
-use systemctl::Unit;
+use serde::{Deserialize, Serialize};
+use systemctl::SystemCtl;
 
+use crate::breaker::Tripped;
+use crate::config::{
+    Action, Config, ConfigDiff, ConfigSource, HostConfig, Role, ServiceConfig, append_service,
+    diff_services, favicon_type, load_and_validate, remove_service,
+};
+use crate::jobs::{JobCreated, run_action_waiting};
+use crate::poller::status_key;
+use crate::request_id::RequestId;
+use crate::templates::{fallback_cards, fallback_service, fallback_welcome};
 use crate::{AppState, ServiceInfo};
 
-pub async fn handle_services(State(state): State<AppState>) -> Response {
-    let env = state.template_env;
+/// Runs `f` on the blocking pool, once a subprocess slot is free. The helpers wait on
+/// `systemctl`/`journalctl`, which would otherwise hold up the async workers
+async fn blocking<F>(f: F) -> Response
+where
+    F: FnOnce() -> Response + Send + 'static,
+{
+    spawn_blocking_limited(f).await.unwrap_or_else(|e| {
+        error!("Blocking task failed: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
+    })
+}
 
-    let units = state
-        .config
-        .service
+/// Why a check turned a request down: the status and the message sent back. Smaller than the
+/// `Response` it becomes, so the checks can return it in their `Err`
+#[derive(Debug, PartialEq)]
+pub struct Refusal(StatusCode, Cow<'static, str>);
+
+impl Refusal {
+    fn new(status: StatusCode, message: impl Into<Cow<'static, str>>) -> Self {
+        Refusal(status, message.into())
+    }
+}
+
+impl IntoResponse for Refusal {
+    fn into_response(self) -> Response {
+        (self.0, self.1).into_response()
+    }
+}
+
+/// Whether the client sent `Accept: application/json`, in which case the HTML routes answer with
+/// the data they would render instead
+pub struct WantsJson(pub bool);
+
+impl<S: Send + Sync> FromRequestParts<S> for WantsJson {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let json = parts
+            .headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|media| media.split(';').next().map(str::trim) == Some("application/json"));
+
+        Ok(WantsJson(json))
+    }
+}
+
+/// Tells caches that the response depends on `Accept`
+fn vary_accept(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("Accept"));
+    response
+}
+
+/// Layout of the services list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum View {
+    /// One line per service
+    Compact,
+    #[default]
+    Detailed,
+}
+
+impl View {
+    fn as_str(&self) -> &'static str {
+        match self {
+            View::Compact => "compact",
+            View::Detailed => "detailed",
+        }
+    }
+
+    /// The `view` cookie set by a previous `?view=`
+    fn from_cookie(headers: &HeaderMap) -> Option<View> {
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .find_map(|cookie| match cookie.trim() {
+                "view=compact" => Some(View::Compact),
+                "view=detailed" => Some(View::Detailed),
+                _ => None,
+            })
+    }
+}
+
+/// Layout of the detailed view
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// The unit information, then the logs
+    #[default]
+    Stacked,
+    /// The unit information and the logs side by side
+    Columns,
+}
+
+impl Layout {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Layout::Stacked => "stacked",
+            Layout::Columns => "columns",
+        }
+    }
+}
+
+/// The layout of the detailed view stored in the `layout` cookie by a previous `?layout=`
+pub struct CurrentLayout(pub Layout);
+
+impl FromRequestParts<AppState> for CurrentLayout {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let layout = parts
+            .headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .find_map(|cookie| match cookie.trim() {
+                "layout=stacked" => Some(Layout::Stacked),
+                "layout=columns" => Some(Layout::Columns),
+                _ => None,
+            })
+            .unwrap_or_default();
+
+        Ok(CurrentLayout(layout))
+    }
+}
+
+/// The `?tag=` of the request, which can be given several times. Services need all of them
+pub struct Tags(pub Vec<String>);
+
+impl FromRequestParts<AppState> for Tags {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let pairs = Query::<Vec<(String, String)>>::try_from_uri(&parts.uri)
+            .map(|Query(pairs)| pairs)
+            .unwrap_or_default();
+
+        Ok(Tags(
+            pairs
+                .into_iter()
+                .filter(|(key, _)| key == "tag")
+                .map(|(_, value)| value)
+                .collect(),
+        ))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ServicesQuery {
+    status: Option<String>,
+    view: Option<View>,
+    /// Comma separated `ServiceInfo` fields returned as JSON
+    fields: Option<String>,
+}
+
+/// Fields `?fields=` can select. `service_name` and `friendly_name` come from `config`
+const SERVICE_FIELDS: [&str; 23] = [
+    "service_name",
+    "friendly_name",
+    "config",
+    "status",
+    "active",
+    "enabled",
+    "running",
+    "exited",
+    "pid",
+    "status_code",
+    "uptime",
+    "down_for",
+    "started",
+    "active_since",
+    "failed",
+    "restarts",
+    "description",
+    "unavailable",
+    "not_found",
+    "host",
+    "availability",
+    "socket",
+    "over_budget",
+];
+
+/// Fields known without querying each unit, so asking only for these skips the per unit queries
+const LISTED_FIELDS: [&str; 12] = [
+    "service_name",
+    "friendly_name",
+    "config",
+    "status",
+    "active",
+    "running",
+    "exited",
+    "failed",
+    "unavailable",
+    "not_found",
+    "host",
+    "availability",
+];
+
+fn parse_fields(raw: Option<&str>) -> Result<Option<Vec<String>>, Refusal> {
+    let Some(raw) = raw else {
+        return Ok(None);
+    };
+
+    let fields: Vec<String> = raw
+        .split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(str::to_owned)
+        .collect();
+
+    if let Some(invalid) = fields
         .iter()
-        .filter_map(|s| match state.systemctl.create_unit(&s.service_name) {
-            Ok(unit) => Some(unit),
-            Err(e) => {
-                error!("Failed to create unit for {}: {}", &s.service_name, e);
-                None
-            }
-        })
-        .collect::<Vec<Unit>>();
+        .find(|field| !SERVICE_FIELDS.contains(&field.as_str()))
+    {
+        return Err(Refusal::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Unknown field {invalid}, expected some of {}",
+                SERVICE_FIELDS.join(", ")
+            ),
+        ));
+    }
 
-    let services_info: Vec<ServiceInfo> = units
-        .into_iter()
-        .filter_map(|unit| {
-            get_unit_info(&unit, &state.config.service)
-                .map_err(|e| error!("Error geting unit info: {e}"))
-                .ok()
+    Ok(Some(fields))
+}
+
+/// Keeps only `fields` of the JSON of `service`
+fn project(service: &ServiceInfo, fields: &[String]) -> serde_json::Value {
+    let value = serde_json::to_value(service).unwrap_or_default();
+
+    let projected = fields
+        .iter()
+        .map(|field| {
+            let field_value = match field.as_str() {
+                "service_name" | "friendly_name" => &value["config"][field],
+                _ => &value[field],
+            };
+            (field.clone(), field_value.clone())
         })
         .collect();
 
-    let cards_template = env
-        .get_template("cards.html")
-        .context("Could not load template 'cards'");
+    serde_json::Value::Object(projected)
+}
+
+pub async fn handle_services(
+    headers: HeaderMap,
+    WantsJson(json): WantsJson,
+    Tags(tags): Tags,
+    Query(query): Query<ServicesQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let problems_only = query.status.as_deref() == Some("problem");
+
+    if !json && state.config().service.is_empty() {
+        return render_welcome(&state);
+    }
+
+    match json {
+        true => vary_accept(services_json(state, None, problems_only, tags, query.fields).await),
+        false => {
+            vary_accept(cards_response(state, headers, None, problems_only, tags, query.view).await)
+        }
+    }
+}
 
-    match cards_template {
-        Ok(_) => {}
+/// First run page, shown instead of an empty list when no service is configured
+fn render_welcome(state: &AppState) -> Response {
+    let config = state.config();
+
+    let response = state
+        .template_env
+        .get_template("welcome.html")
+        .and_then(|template| {
+            template.render(context! {
+                config_source => state.config_source.to_string(),
+                patterns => !config.pattern.is_empty(),
+                watch_config => config.watch_config,
+                app_name => config.app_name,
+                hostname => config.instance_hostname(),
+            })
+        });
+
+    match response {
+        Ok(html) => Html(html).into_response(),
         Err(e) => {
-            error!("Could not get template 'cards': {e}");
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+            error!("Could not render template 'welcome': {e:#}");
+            Html(fallback_welcome(&state.config_source.to_string())).into_response()
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct WallCell<'a> {
+    friendly_name: &'a str,
+    host: Option<&'a str>,
+    /// `ok`, `problem` or `unknown`, the color of the cell
+    state: &'static str,
+    /// Shown on hover, e.g. `failed`
+    status: &'a str,
+}
+
+/// Status matrix for a wall display: one colored cell per service, reloaded every
+/// `wall_refresh_secs`
+pub async fn handle_wall(Tags(tags): Tags, State(state): State<AppState>) -> Response {
+    blocking(move || {
+        let config = state.config();
+        let services_info = collect_hosts_info(&state, None, &tags, false);
+
+        let cells: Vec<WallCell> = services_info
+            .iter()
+            .map(|s| WallCell {
+                friendly_name: &s.config.friendly_name,
+                host: s.host.as_deref(),
+                state: match s {
+                    s if s.unavailable => "unknown",
+                    s if s.is_problem() => "problem",
+                    s if s.active => "ok",
+                    _ => "unknown",
+                },
+                status: &s.status,
+            })
+            .collect();
+
+        let response = state
+            .template_env
+            .get_template("wall.html")
+            .and_then(|template| {
+                template.render(context! {
+                    cells,
+                    refresh => config.wall_refresh_secs,
+                    app_name => config.app_name,
+                })
+            });
+
+        match response {
+            Ok(html) => Html(html).into_response(),
+            Err(e) => {
+                error!("Could not render template 'wall': {e:#}");
+                Html(fallback_cards(services_info.iter())).into_response()
+            }
         }
+    })
+    .await
+}
+
+/// The services of one of the `[[host]]` entries, as cards or JSON
+pub async fn handle_host_services(
+    Path(host): Path<String>,
+    headers: HeaderMap,
+    WantsJson(json): WantsJson,
+    Tags(tags): Tags,
+    Query(query): Query<ServicesQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(host) = state.config().find_host(&host).cloned() else {
+        return (StatusCode::NOT_FOUND, "Host not found").into_response();
     };
 
-    let response = cards_template
-        .unwrap()
-        .render(context! {services => services_info})
-        .map_err(|e| error!("Could not render template 'cards': {e}"));
+    let problems_only = query.status.as_deref() == Some("problem");
+    let host = Some(host);
 
-    if response.is_err() {
-        return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+    match json {
+        true => vary_accept(services_json(state, host, problems_only, tags, query.fields).await),
+        false => {
+            vary_accept(cards_response(state, headers, host, problems_only, tags, query.view).await)
+        }
     }
-
-    Html(response.unwrap()).into_response()
 }
 
-pub async fn handle_service(
-    Path(service): Path<String>,
+pub async fn handle_problems(
+    headers: HeaderMap,
+    WantsJson(json): WantsJson,
+    Tags(tags): Tags,
+    Query(query): Query<ServicesQuery>,
     State(state): State<AppState>,
 ) -> Response {
-    let config = state
-        .config
-        .service
-        .iter()
-        .find(|a| a.service_name == service)
-        .with_context(|| format!("Unable to find config of unit {service}"))
-        .map_err(|e| error!("{e}"));
+    match json {
+        true => vary_accept(services_json(state, None, true, tags, query.fields).await),
+        false => vary_accept(cards_response(state, headers, None, true, tags, query.view).await),
+    }
+}
 
-    if config.is_err() {
-        return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+async fn services_json(
+    state: AppState,
+    host: Option<HostConfig>,
+    problems_only: bool,
+    tags: Vec<String>,
+    fields: Option<String>,
+) -> Response {
+    let fields = match parse_fields(fields.as_deref()) {
+        Ok(fields) => fields,
+        Err(refusal) => return refusal.into_response(),
+    };
+
+    // `is_problem` needs the full information
+    let listed_only = !problems_only
+        && fields.as_ref().is_some_and(|fields| {
+            fields
+                .iter()
+                .all(|field| LISTED_FIELDS.contains(&field.as_str()))
+        });
+
+    blocking(move || {
+        let mut services_info = collect_hosts_info(&state, host.as_ref(), &tags, listed_only);
+
+        if problems_only {
+            services_info.retain(ServiceInfo::is_problem);
+        }
+
+        match fields {
+            Some(fields) => Json(
+                services_info
+                    .iter()
+                    .map(|service| project(service, &fields))
+                    .collect::<Vec<_>>(),
+            )
+            .into_response(),
+            None => Json(services_info).into_response(),
+        }
+    })
+    .await
+}
+
+/// Renders the cards in the requested view, or the one remembered in the cookie. Choosing a view
+/// with `?view=` stores it for the next requests
+async fn cards_response(
+    state: AppState,
+    headers: HeaderMap,
+    host: Option<HostConfig>,
+    problems_only: bool,
+    tags: Vec<String>,
+    requested: Option<View>,
+) -> Response {
+    let view = requested
+        .or_else(|| View::from_cookie(&headers))
+        .unwrap_or_default();
+
+    let mut response =
+        blocking(move || render_cards(&state, &headers, host.as_ref(), problems_only, &tags, view))
+            .await;
+
+    if let Some(view) = requested {
+        let cookie = format!(
+            "view={}; Path=/; Max-Age=31536000; SameSite=Lax",
+            view.as_str()
+        );
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().insert(header::SET_COOKIE, cookie);
+        }
     }
 
-    let config = config.unwrap();
+    response
+}
+
+/// Makes browsers and proxies revalidate every status response instead of showing a stale copy.
+/// `no-cache` rather than `no-store` so the card ETags can still answer with 304
+pub async fn no_cache(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .entry(header::CACHE_CONTROL)
+        .or_insert(HeaderValue::from_static("no-cache, must-revalidate"));
+    response
+}
+
+/// Weak ETag of a rendered page. Taken from the body itself, so any change the page shows, like
+/// a longer uptime, makes a new one
+fn body_etag(body: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("W/\"{:x}\"", hasher.finish())
+}
+
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    let strip = |tag: &str| tag.trim().trim_start_matches("W/").to_owned();
+
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || strip(tag) == strip(etag))
+}
+
+/// Name of the batched `systemctl show` of the cards for `Breakers`
+const SHOW_UNITS_QUERY: &str = "systemctl show";
+
+/// With `listed_only` only the state reported by `list-units` is filled in. Only the services
+/// with every one of `tags` are queried
+fn collect_services_info(state: &AppState, tags: &[String], listed_only: bool) -> Vec<ServiceInfo> {
+    let config = state.config();
+
+    // Problems are picked by the callers, from the full information: an active service can be
+    // one too when it keeps restarting
+    let mut services: Vec<&ServiceConfig> = config.service.iter().collect();
+
+    services.retain(|s| tags.iter().all(|tag| s.tags.contains(tag)));
+
+    // Stable, so both groups keep the order of the config
+    services.sort_by_key(|s| !s.pinned);
+
+    let names: Vec<String> = services.iter().map(|s| s.service_name.clone()).collect();
+
+    // A unit that cannot be queried still gets a card, so it is not silently missing
+    let unavailable = |s: &ServiceConfig, e: anyhow::Error| {
+        match e.is::<Tripped>() {
+            true => debug!("Skipped {}: {e}", s.service_name),
+            false => error!("Error getting unit info of {}: {e}", s.service_name),
+        }
+        ServiceInfo::unavailable(s.clone())
+    };
+
+    if !listed_only {
+        // Their sockets come after the services
+        let sockets: Vec<String> = services.iter().filter_map(|s| s.socket.clone()).collect();
+        let units = [names.as_slice(), &sockets].concat();
+
+        // Every property of every service from one `systemctl show`. It has a breaker of its own:
+        // when it fails it fails for all the services, which are then shown as unavailable
+        let shown = state
+            .breakers
+            .call(SHOW_UNITS_QUERY, &config, || systemctl_show_units(&units))
+            .map_err(|e| match e.is::<Tripped>() {
+                true => debug!("Skipped {SHOW_UNITS_QUERY}: {e}"),
+                false => error!("{e}"),
+            })
+            .unwrap_or_default();
+
+        let shown_sockets = shown.get(names.len()..).unwrap_or_default();
+        let socket_of = |s: &ServiceConfig| {
+            let socket = s.socket.as_ref()?;
+            shown_sockets.get(sockets.iter().position(|name| name == socket)?)
+        };
 
-    let env = state.template_env;
+        return services
+            .into_iter()
+            .enumerate()
+            .map(|(i, s)| {
+                shown
+                    .get(i)
+                    .with_context(|| format!("{} could not be queried", s.service_name))
+                    .and_then(|properties| {
+                        get_shown_unit_info(s, properties, socket_of(s), &config)
+                    })
+                    .unwrap_or_else(|e| unavailable(s, e))
+            })
+            .collect();
+    }
 
-    let status = systemd_status_html(&service)
+    let listed = systemctl_list_unit_states(&names)
         .map_err(|e| error!("{e}"))
         .ok();
 
-    let journal = match config.show_logs {
-        true => journalctl_html(&service).map_err(|e| error!("{e}")).ok(),
-        false => Some(String::new()),
+    services
+        .into_iter()
+        .map(|s| {
+            match listed
+                .as_ref()
+                .map(|listed| listed.iter().find(|unit| unit.unit == s.service_name))
+            {
+                Some(Some(unit)) => get_listed_unit_state(s, unit),
+                // Only the units systemd knows are listed
+                Some(None) => ServiceInfo::not_found(s.clone()),
+                None => ServiceInfo::unavailable(s.clone()),
+            }
+        })
+        .collect()
+}
+
+/// `query_hosts_info` with the availability and the budget alerts of the poller
+fn collect_hosts_info(
+    state: &AppState,
+    host: Option<&HostConfig>,
+    tags: &[String],
+    listed_only: bool,
+) -> Vec<ServiceInfo> {
+    let mut services = query_hosts_info(state, host, tags, listed_only);
+
+    for service in &mut services {
+        let key = status_key(service);
+        service.availability = state.history.availability(&key);
+        service.over_budget = state.budgets.alert(&key);
+    }
+
+    services
+}
+
+/// The services of `host`, or of every `[[host]]` entry at the same time when none is given.
+/// Without `[[host]]` entries the services of the managed machine
+fn query_hosts_info(
+    state: &AppState,
+    host: Option<&HostConfig>,
+    tags: &[String],
+    listed_only: bool,
+) -> Vec<ServiceInfo> {
+    let collect = |host: &HostConfig| {
+        on_host(&host.target, || {
+            let mut services = collect_services_info(state, tags, listed_only);
+            for service in &mut services {
+                service.host = Some(host.name.clone());
+            }
+            services
+        })
     };
 
-    let template = env
-        .get_template("commands.html")
-        .map_err(|e| error!("Could not load template 'commands': {e}"));
+    if let Some(host) = host {
+        return collect(host);
+    }
+
+    let config = state.config();
 
-    if template.is_err() {
-        return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+    if config.hosts().is_empty() {
+        return collect_services_info(state, tags, listed_only);
+    }
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = config
+            .hosts()
+            .iter()
+            .map(|host| scope.spawn(move || collect(host)))
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    })
+}
+
+/// A service with its state on each of the `[[host]]` entries, `None` where it is not listed
+#[derive(Serialize)]
+struct MatrixRow {
+    config: ServiceConfig,
+    cells: Vec<Option<ServiceInfo>>,
+}
+
+fn host_matrix(hosts: &[HostConfig], services: Vec<ServiceInfo>) -> Vec<MatrixRow> {
+    let mut rows: Vec<MatrixRow> = Vec::new();
+
+    for service in services {
+        let Some(column) = hosts
+            .iter()
+            .position(|host| service.host.as_ref() == Some(&host.name))
+        else {
+            continue;
+        };
+
+        let row = match rows
+            .iter()
+            .position(|row| row.config.service_name == service.config.service_name)
+        {
+            Some(i) => &mut rows[i],
+            None => {
+                rows.push(MatrixRow {
+                    config: service.config.clone(),
+                    cells: vec![None; hosts.len()],
+                });
+                rows.last_mut().unwrap()
+            }
+        };
+
+        row.cells[column] = Some(service);
+    }
+
+    rows
+}
+
+/// Number of services in each coarse state
+#[derive(Default, Serialize)]
+struct Summary {
+    total: usize,
+    active: usize,
+    inactive: usize,
+    failed: usize,
+    other: usize,
+    /// `systemctl is-system-running` at the last poll, e.g. `degraded`
+    system_state: Option<String>,
+}
+
+pub async fn handle_summary(State(state): State<AppState>) -> Response {
+    blocking(move || {
+        let names: Vec<String> = state
+            .config()
+            .service
+            .iter()
+            .map(|s| s.service_name.clone())
+            .collect();
+
+        let states = match systemctl_is_active(&names) {
+            Ok(states) => states,
+            Err(e) => {
+                error!("{e}");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
+                    .into_response();
+            }
+        };
+
+        let mut summary = Summary {
+            total: states.len(),
+            system_state: state.system_state.read().unwrap().clone(),
+            ..Default::default()
+        };
+
+        for unit_state in &states {
+            match unit_state.as_str() {
+                "active" => summary.active += 1,
+                "inactive" => summary.inactive += 1,
+                "failed" => summary.failed += 1,
+                _ => summary.other += 1,
+            }
+        }
+
+        Json(summary).into_response()
+    })
+    .await
+}
+
+#[derive(Serialize)]
+struct ServiceName<'a> {
+    service_name: &'a str,
+    friendly_name: &'a str,
+    /// Lowercase friendly name with dashes, e.g. `display-manager-sddm`
+    slug: String,
+}
+
+fn slug(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Names of the configured services for the quick switcher. Nothing is queried
+pub async fn handle_service_names(State(state): State<AppState>) -> Response {
+    let config = state.config();
+
+    let names: Vec<ServiceName> = config
+        .service
+        .iter()
+        .map(|s| ServiceName {
+            service_name: &s.service_name,
+            friendly_name: &s.friendly_name,
+            slug: slug(&s.friendly_name),
+        })
+        .collect();
+
+    Json(names).into_response()
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum HealthStatus {
+    Ok,
+    Degraded,
+    Down,
+}
+
+#[derive(Serialize)]
+struct HealthCounts {
+    total: usize,
+    running: usize,
+    failed: usize,
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: HealthStatus,
+    services: HealthCounts,
+    timestamp: String,
+}
+
+/// Health of the managed services for a status page: `degraded` when an enabled service is not
+/// active and `down` when none of them is. Made of one `is-active` and one `is-enabled` call per
+/// host
+pub async fn handle_health(State(state): State<AppState>) -> Response {
+    blocking(move || {
+        let config = state.config();
+
+        let names: Vec<String> = config
+            .service
+            .iter()
+            .map(|s| s.service_name.clone())
+            .collect();
+
+        let hosts: Vec<Option<&HostConfig>> = match config.hosts() {
+            [] => vec![None],
+            hosts => hosts.iter().map(Some).collect(),
+        };
+
+        let mut states: Vec<(String, bool)> = Vec::new();
+
+        for host in hosts {
+            let host_states = in_host(host, || {
+                let active = systemctl_is_active(&names)?;
+                // A unit without a unit file makes is-enabled fail, count them all as enabled
+                let enabled = systemctl_is_enabled(&names)
+                    .map_err(|e| error!("{e}"))
+                    .unwrap_or_else(|_| vec!["enabled".to_owned(); names.len()]);
+                anyhow::Ok((active, enabled))
+            });
+
+            match host_states {
+                Ok((active, enabled)) => states.extend(
+                    active
+                        .into_iter()
+                        .zip(enabled)
+                        .map(|(active, enabled)| (active, enabled.starts_with("enabled"))),
+                ),
+                Err(e) => {
+                    error!("{e}");
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
+                        .into_response();
+                }
+            }
+        }
+
+        let (status, services) = assess_health(&states);
+
+        Json(Health {
+            status,
+            services,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        })
+        .into_response()
+    })
+    .await
+}
+
+/// Counts of `states`, the `ActiveState` of each service and whether it is enabled, and the
+/// status they add up to
+fn assess_health(states: &[(String, bool)]) -> (HealthStatus, HealthCounts) {
+    let services = HealthCounts {
+        total: states.len(),
+        running: states
+            .iter()
+            .filter(|(active, _)| active == "active")
+            .count(),
+        failed: states
+            .iter()
+            .filter(|(active, _)| active == "failed")
+            .count(),
     };
 
-    let response = template
-        .unwrap()
-        .render(context! {status, journal })
-        .map_err(|e| error!("Could not render template 'commands': {e}"));
+    let enabled: Vec<&String> = states
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(active, _)| active)
+        .collect();
 
-    if response.is_err() {
-        return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+    let down = enabled
+        .iter()
+        .filter(|active| active.as_str() != "active")
+        .count();
+
+    let status = match down {
+        0 => HealthStatus::Ok,
+        down if down == enabled.len() => HealthStatus::Down,
+        _ => HealthStatus::Degraded,
+    };
+
+    (status, services)
+}
+
+/// Instances of the same template unit (`worker@1.service`, `worker@2.service`...)
+#[derive(Serialize)]
+struct InstanceGroup {
+    name: String,
+    running: usize,
+    services: Vec<ServiceInfo>,
+}
+
+/// Moves the instances of template units into one group per template. Templates with a single
+/// instance stay with the rest of the services
+fn group_instances(services: Vec<ServiceInfo>) -> (Vec<InstanceGroup>, Vec<ServiceInfo>) {
+    let mut groups: Vec<InstanceGroup> = Vec::new();
+    let mut singles = Vec::new();
+
+    for service in services {
+        let Some((prefix, _)) = service.config.service_name.split_once('@') else {
+            singles.push(service);
+            continue;
+        };
+
+        let name = format!("{prefix}@");
+
+        match groups.iter_mut().find(|g| g.name == name) {
+            Some(group) => group.services.push(service),
+            None => groups.push(InstanceGroup {
+                name,
+                running: 0,
+                services: vec![service],
+            }),
+        }
+    }
+
+    let (mut groups, lone): (Vec<_>, Vec<_>) =
+        groups.into_iter().partition(|g| g.services.len() > 1);
+
+    singles.extend(lone.into_iter().flat_map(|g| g.services));
+
+    for group in &mut groups {
+        group.running = group.services.iter().filter(|s| s.running).count();
+    }
+
+    (groups, singles)
+}
+
+fn render_cards(
+    state: &AppState,
+    headers: &HeaderMap,
+    host: Option<&HostConfig>,
+    problems_only: bool,
+    tags: &[String],
+    view: View,
+) -> Response {
+    let env = &state.template_env;
+
+    let config = state.config();
+
+    let mut services_info = collect_hosts_info(state, host, tags, false);
+
+    if problems_only {
+        services_info.retain(ServiceInfo::is_problem);
     }
 
+    let maintenance = state.maintenance.load(Ordering::Relaxed);
+
+    let system_state = state.system_state.read().unwrap().clone();
+
+    // Every host side by side, unless one was asked for
+    let (hosts, matrix, services_info) = match (host, config.hosts()) {
+        (None, hosts) if !hosts.is_empty() => (
+            hosts.iter().map(|h| h.name.as_str()).collect(),
+            host_matrix(hosts, services_info),
+            Vec::new(),
+        ),
+        _ => (Vec::new(), Vec::new(), services_info),
+    };
+
+    let (groups, services_info) = match config.group_instances {
+        true => group_instances(services_info),
+        false => (Vec::new(), services_info),
+    };
+
+    let response = env.get_template("cards.html").and_then(|template| {
+        template.render(context! {
+            groups,
+            services => services_info,
+            hosts,
+            matrix,
+            host => host.map(|h| &h.name),
+            problems_only,
+            tags,
+            maintenance,
+            system_state,
+            view,
+            availability_window => config.availability_window_days,
+            app_name => config.app_name,
+            hostname => config.instance_hostname(),
+        })
+    });
+
     match response {
-        Ok(r) => Html(r).into_response(),
-        Err(_) => (StatusCode::BAD_REQUEST).into_response(),
+        Ok(html) => {
+            let etag = body_etag(&html);
+            match etag_matches(headers, &etag) {
+                true => (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response(),
+                false => ([(header::ETAG, etag)], Html(html)).into_response(),
+            }
+        }
+        Err(e) => {
+            error!("Could not render template 'cards': {e:#}");
+            let services = groups
+                .iter()
+                .flat_map(|group| &group.services)
+                .chain(&services_info)
+                .chain(matrix.iter().flat_map(|row| row.cells.iter().flatten()));
+            Html(fallback_cards(services)).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ServiceQuery {
+    #[serde(default)]
+    boot: i32,
+    priority: Option<LogLevel>,
+    /// One of the `[[host]]` entries
+    host: Option<String>,
+    /// Only the logs written since the service was last started
+    #[serde(default)]
+    since_start: bool,
+    /// Only the log lines matching this regular expression
+    grep: Option<String>,
+    /// Stored in a cookie for the next requests
+    layout: Option<Layout>,
+    /// Overrides `log_timestamps`
+    timestamps: Option<TimestampFormat>,
+    /// Shows the logs even with `logs_collapsed_by_default`
+    #[serde(default)]
+    expand_logs: bool,
+}
+
+/// Longest `grep` pattern accepted
+const MAX_GREP_LENGTH: usize = 256;
+
+/// An empty pattern is no filter. Control characters are refused, journalctl gets the rest as is.
+/// journalctl matches the messages before `redact_patterns` hides anything, so while they are set
+/// only admins can grep: anyone else could find a hidden value by trying patterns
+fn check_grep(
+    grep: Option<String>,
+    role: Role,
+    config: &Config,
+) -> Result<Option<String>, Refusal> {
+    match grep {
+        Some(pattern) if pattern.len() > MAX_GREP_LENGTH => Err(Refusal::new(
+            StatusCode::BAD_REQUEST,
+            format!("grep must be at most {MAX_GREP_LENGTH} characters"),
+        )),
+        Some(pattern) if pattern.chars().any(char::is_control) => Err(Refusal::new(
+            StatusCode::BAD_REQUEST,
+            "grep cannot contain control characters",
+        )),
+        Some(pattern) if pattern.is_empty() => Ok(None),
+        Some(_) if !config.redact_regexes.is_empty() && role < Role::Admin => Err(Refusal::new(
+            StatusCode::FORBIDDEN,
+            "grep is only available to admins while redact_patterns is set",
+        )),
+        grep => Ok(grep),
+    }
+}
+
+/// Number of journal entries shown on the detail view
+const DETAIL_LOG_LINES: u32 = 100;
+
+pub async fn handle_service(
+    Path(service): Path<String>,
+    WantsJson(json): WantsJson,
+    CurrentRole(role): CurrentRole,
+    CurrentLayout(remembered): CurrentLayout,
+    Query(query): Query<ServiceQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    if !(MAX_BOOT_OFFSET..=0).contains(&query.boot) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("boot must be between {MAX_BOOT_OFFSET} and 0"),
+        )
+            .into_response();
+    }
+
+    let app_config = state.config();
+
+    let config = app_config
+        .service
+        .iter()
+        .find(|a| a.service_name == service)
+        .with_context(|| format!("Unable to find config of unit {service}"))
+        .map_err(|e| error!("{e}"));
+
+    if config.is_err() {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+    }
+
+    let grep = match check_grep(query.grep, role, &app_config) {
+        Ok(grep) => grep,
+        Err(refusal) => return refusal.into_response(),
+    };
+
+    let filter = JournalFilter {
+        lines: DETAIL_LOG_LINES,
+        boot: Some(query.boot),
+        level: query.priority,
+        since: None,
+        grep,
+        after_cursor: None,
+        timestamps: query.timestamps.unwrap_or(app_config.log_timestamps),
+        identifier: config
+            .as_ref()
+            .ok()
+            .and_then(|c| c.journal_identifier.clone()),
+    };
+
+    let mut config = config.unwrap().clone();
+
+    let host = match query.host.as_deref().map(|name| app_config.find_host(name)) {
+        Some(None) => return (StatusCode::NOT_FOUND, "Host not found").into_response(),
+        Some(Some(host)) => Some(host.clone()),
+        None => None,
+    };
+
+    // The collapsed section reads the logs through `/logs/html`, which is for the local machine
+    if query.expand_logs || host.is_some() {
+        config.logs_collapsed_by_default = false;
+    }
+
+    if json {
+        return vary_accept(
+            blocking(move || {
+                in_host(host.as_ref(), || {
+                    service_detail_json(&state, &config, host.as_ref())
+                })
+            })
+            .await,
+        );
+    }
+
+    let layout = query.layout.unwrap_or(remembered);
+
+    let mut response = blocking(move || {
+        in_host(host.as_ref(), || {
+            let mut filter = filter;
+
+            // Without a start time, e.g. a unit that never ran, the last lines are shown
+            if query.since_start {
+                filter.since =
+                    systemd_show_timestamp("ExecMainStartTimestamp", &config.service_name)
+                        .map_err(|e| error!("{e}"))
+                        .ok()
+                        .flatten();
+            }
+
+            render_service(&state, &config, &filter, role, layout, host.as_ref(), None)
+        })
+    })
+    .await;
+
+    if let Some(layout) = query.layout {
+        let cookie = format!(
+            "layout={}; Path=/; Max-Age=31536000; SameSite=Lax",
+            layout.as_str()
+        );
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().insert(header::SET_COOKIE, cookie);
+        }
+    }
+
+    vary_accept(response)
+}
+
+/// Runs `f` against `host`, or the managed machine when there is none
+fn in_host<T>(host: Option<&HostConfig>, f: impl FnOnce() -> T) -> T {
+    match host {
+        Some(host) => on_host(&host.target, f),
+        None => f(),
+    }
+}
+
+fn systemctl_for(state: &AppState, host: Option<&HostConfig>) -> SystemCtl {
+    build_systemctl(&state.config(), host.map(|host| host.target.as_str()))
+}
+
+/// `200` when the service is active and `503` otherwise, for monitors that only look at the
+/// status code
+pub async fn handle_service_status(
+    Path(service): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    let app_config = state.config();
+
+    let Some(config) = app_config
+        .service
+        .iter()
+        .find(|s| s.service_name == service)
+        .cloned()
+    else {
+        return (StatusCode::NOT_FOUND, "Service not found").into_response();
+    };
+
+    blocking(move || {
+        let info = systemctl_for(&state, None)
+            .create_unit(&service)
+            .map_err(anyhow::Error::from)
+            .and_then(|unit| get_unit_info(&unit, &app_config))
+            .unwrap_or_else(|e| {
+                error!("Error getting unit info of {service}: {e}");
+                ServiceInfo::unavailable(config)
+            });
+
+        let (code, state) = match (info.active, info.failed, info.unavailable) {
+            (_, _, true) => (StatusCode::SERVICE_UNAVAILABLE, "unavailable"),
+            (true, _, _) => (StatusCode::OK, "active"),
+            (_, true, _) => (StatusCode::SERVICE_UNAVAILABLE, "failed"),
+            _ => (StatusCode::SERVICE_UNAVAILABLE, "inactive"),
+        };
+
+        (code, state).into_response()
+    })
+    .await
+}
+
+fn service_detail_json(
+    state: &AppState,
+    config: &ServiceConfig,
+    host: Option<&HostConfig>,
+) -> Response {
+    let detail = systemctl_for(state, host)
+        .create_unit(&config.service_name)
+        .map_err(anyhow::Error::from)
+        .and_then(|unit| get_unit_detail(&unit, &state.config()));
+
+    match detail {
+        Ok(detail) => Json(detail).into_response(),
+        Err(e) => {
+            error!("Error getting unit detail: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
+        }
+    }
+}
+
+pub async fn handle_mask(
+    Path(service): Path<String>,
+    CurrentRole(role): CurrentRole,
+    CurrentLayout(layout): CurrentLayout,
+    RequestId(request_id): RequestId,
+    Query(TargetQuery { target }): Query<TargetQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    blocking(move || {
+        handle_action(
+            &state,
+            &service,
+            target,
+            Action::Mask,
+            Vec::new(),
+            role,
+            layout,
+            &request_id,
+        )
+    })
+    .await
+}
+
+pub async fn handle_unmask(
+    Path(service): Path<String>,
+    CurrentRole(role): CurrentRole,
+    CurrentLayout(layout): CurrentLayout,
+    RequestId(request_id): RequestId,
+    Query(TargetQuery { target }): Query<TargetQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    blocking(move || {
+        handle_action(
+            &state,
+            &service,
+            target,
+            Action::Unmask,
+            Vec::new(),
+            role,
+            layout,
+            &request_id,
+        )
+    })
+    .await
+}
+
+pub async fn handle_stop(
+    Path(service): Path<String>,
+    CurrentRole(role): CurrentRole,
+    CurrentLayout(layout): CurrentLayout,
+    RequestId(request_id): RequestId,
+    Query(TargetQuery { target }): Query<TargetQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    blocking(move || {
+        handle_action(
+            &state,
+            &service,
+            target,
+            Action::Stop,
+            Vec::new(),
+            role,
+            layout,
+            &request_id,
+        )
+    })
+    .await
+}
+
+pub async fn handle_restart(
+    Path(service): Path<String>,
+    CurrentRole(role): CurrentRole,
+    CurrentLayout(layout): CurrentLayout,
+    RequestId(request_id): RequestId,
+    Query(TargetQuery { target }): Query<TargetQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    blocking(move || {
+        handle_action(
+            &state,
+            &service,
+            target,
+            Action::Restart,
+            Vec::new(),
+            role,
+            layout,
+            &request_id,
+        )
+    })
+    .await
+}
+
+/// Runs one of the `[[custom_action]]` entries the service lists in its `custom_actions`
+pub async fn handle_custom_action(
+    Path((service, name)): Path<(String, String)>,
+    CurrentRole(role): CurrentRole,
+    CurrentLayout(layout): CurrentLayout,
+    RequestId(request_id): RequestId,
+    State(state): State<AppState>,
+) -> Response {
+    blocking(move || {
+        let config = match check_can_act(&state, &service, role) {
+            Ok(config) => config,
+            Err(refusal) => return refusal.into_response(),
+        };
+
+        let Some(action) = state
+            .config()
+            .custom_action
+            .iter()
+            .find(|action| action.name == name)
+            .cloned()
+        else {
+            return (StatusCode::NOT_FOUND, "Custom action not found").into_response();
+        };
+
+        if !config.custom_actions.contains(&name) {
+            return (StatusCode::FORBIDDEN, "Action not allowed").into_response();
+        }
+
+        let args = action.args_for(&service);
+
+        if let Err(e) = run_custom_action(&name, &args) {
+            error!("{e} request_id={request_id}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+        }
+
+        info!(
+            "Ran {} request_id={request_id}",
+            command_line(&custom_action_command(&args))
+        );
+
+        let filter = JournalFilter {
+            lines: DETAIL_LOG_LINES,
+            boot: Some(0),
+            level: None,
+            since: None,
+            grep: None,
+            after_cursor: None,
+            timestamps: state.config().log_timestamps,
+            identifier: config.journal_identifier.clone(),
+        };
+
+        let outcome = format!("{} done", action.label());
+
+        render_service(&state, &config, &filter, role, layout, None, Some(&outcome))
+    })
+    .await
+}
+
+/// Unit an action is run on: the service itself or its configured `socket`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    #[default]
+    Service,
+    Socket,
+}
+
+#[derive(Deserialize)]
+pub struct TargetQuery {
+    #[serde(default)]
+    target: Target,
+}
+
+/// Signals that can be sent with the kill action
+pub const ALLOWED_SIGNALS: [&str; 5] = ["SIGTERM", "SIGKILL", "SIGHUP", "SIGUSR1", "SIGUSR2"];
+
+#[derive(Deserialize)]
+pub struct KillQuery {
+    signal: String,
+    #[serde(default)]
+    target: Target,
+}
+
+pub async fn handle_kill(
+    Path(service): Path<String>,
+    CurrentRole(role): CurrentRole,
+    CurrentLayout(layout): CurrentLayout,
+    RequestId(request_id): RequestId,
+    Query(query): Query<KillQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    if !ALLOWED_SIGNALS.contains(&query.signal.as_str()) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("signal must be one of {}", ALLOWED_SIGNALS.join(", ")),
+        )
+            .into_response();
+    }
+
+    let args = vec![format!("--signal={}", query.signal)];
+
+    blocking(move || {
+        handle_action(
+            &state,
+            &service,
+            query.target,
+            Action::Kill,
+            args,
+            role,
+            layout,
+            &request_id,
+        )
+    })
+    .await
+}
+
+/// Whether `action` makes sense for the current state of `service`
+fn action_applies(action: Action, service: &str) -> anyhow::Result<bool> {
+    Ok(match action {
+        Action::Mask => systemd_show_parse::<String>("LoadState", service)? != "masked",
+        Action::Unmask => systemd_show_parse::<String>("LoadState", service)? == "masked",
+        Action::Kill | Action::Stop => matches!(
+            systemd_show_parse::<String>("ActiveState", service)?.as_str(),
+            "active" | "activating" | "deactivating" | "reloading"
+        ),
+        Action::Restart => systemd_show_parse::<String>("LoadState", service)? != "masked",
+    })
+}
+
+/// Checks that `role` can act on `service` right now, whatever the action. Returns the config of
+/// the service or the error response
+fn check_can_act(state: &AppState, service: &str, role: Role) -> Result<ServiceConfig, Refusal> {
+    let app_config = state.config();
+
+    let Some(config) = app_config
+        .service
+        .iter()
+        .find(|a| a.service_name == service)
+    else {
+        return Err(Refusal::new(StatusCode::NOT_FOUND, "Service not found"));
+    };
+
+    if app_config.read_only {
+        return Err(Refusal::new(
+            StatusCode::FORBIDDEN,
+            "Dashboard is read-only",
+        ));
+    }
+
+    if role < Role::Operator {
+        return Err(Refusal::new(
+            StatusCode::FORBIDDEN,
+            "Viewers cannot run actions",
+        ));
+    }
+
+    if state.maintenance.load(Ordering::Relaxed) {
+        return Err(Refusal::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Maintenance in progress",
+        ));
+    }
+
+    Ok(config.clone())
+}
+
+/// Checks that `action` can be run on the `target` unit of `service` right now. Returns the
+/// config of the service and the unit to act on, or the error response
+pub fn check_action(
+    state: &AppState,
+    service: &str,
+    target: Target,
+    action: Action,
+    role: Role,
+) -> Result<(ServiceConfig, String), Refusal> {
+    let config = check_can_act(state, service, role)?;
+
+    if !config.allowed_actions.contains(&action) {
+        return Err(Refusal::new(StatusCode::FORBIDDEN, "Action not allowed"));
+    }
+
+    let unit = match (target, &config.socket) {
+        (Target::Service, _) => service,
+        (Target::Socket, Some(socket)) => socket.as_str(),
+        (Target::Socket, None) => {
+            return Err(Refusal::new(StatusCode::NOT_FOUND, "No socket configured"));
+        }
+    };
+
+    let valid = match action_applies(action, unit) {
+        Ok(valid) => valid,
+        Err(e) => {
+            error!("{e}");
+            return Err(Refusal::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error",
+            ));
+        }
+    };
+
+    if !valid {
+        return Err(Refusal::new(
+            StatusCode::CONFLICT,
+            format!("Cannot {} {unit} in its current state", action.verb()),
+        ));
+    }
+
+    let unit = unit.to_owned();
+
+    Ok((config, unit))
+}
+
+/// How long an action endpoint waits for the action before answering with a job to poll instead
+const ACTION_WAIT: Duration = Duration::from_secs(10);
+
+#[allow(clippy::too_many_arguments)]
+fn handle_action(
+    state: &AppState,
+    service: &str,
+    target: Target,
+    action: Action,
+    args: Vec<String>,
+    role: Role,
+    layout: Layout,
+    request_id: &str,
+) -> Response {
+    let (config, unit) = match check_action(state, service, target, action, role) {
+        Ok(checked) => checked,
+        Err(refusal) => return refusal.into_response(),
+    };
+
+    let result = run_action_waiting(
+        state,
+        action,
+        args,
+        unit,
+        request_id.to_owned(),
+        ACTION_WAIT,
+    );
+
+    let outcome = match result {
+        Ok(Ok(outcome)) => outcome,
+        Ok(Err(e)) => {
+            error!("{e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+        }
+        // Still going, e.g. a stop waiting up to `stop_wait_secs`, it is polled on `/jobs/{job_id}`
+        Err(job_id) => return (StatusCode::ACCEPTED, Json(JobCreated { job_id })).into_response(),
+    };
+
+    let filter = JournalFilter {
+        lines: DETAIL_LOG_LINES,
+        boot: Some(0),
+        level: None,
+        since: None,
+        grep: None,
+        after_cursor: None,
+        timestamps: state.config().log_timestamps,
+        identifier: config.journal_identifier.clone(),
+    };
+
+    render_service(
+        state,
+        &config,
+        &filter,
+        role,
+        layout,
+        None,
+        outcome.as_deref(),
+    )
+}
+
+/// Runs an action checked by `check_action`, logged with the id of the request that asked for it.
+/// Returns how it ended when there is more to it than success, like a stop that needed a kill
+pub fn run_action(
+    state: &AppState,
+    action: Action,
+    args: &[String],
+    service: &str,
+    request_id: &str,
+) -> anyhow::Result<Option<String>> {
+    let outcome = match action {
+        Action::Stop => {
+            let wait = Duration::from_secs(state.config().stop_wait_secs);
+            Some(systemctl_stop(service, wait)?.to_string())
+        }
+        _ => {
+            systemctl_action(action.verb(), args, service)?;
+            None
+        }
+    };
+
+    let command = command_line(&action_command(action.verb(), args, service));
+
+    match &outcome {
+        Some(outcome) => info!("Ran {command}: {outcome} request_id={request_id}"),
+        None => info!("Ran {command} request_id={request_id}"),
+    }
+
+    Ok(outcome)
+}
+
+fn render_service(
+    state: &AppState,
+    config: &ServiceConfig,
+    filter: &JournalFilter,
+    role: Role,
+    layout: Layout,
+    host: Option<&HostConfig>,
+    outcome: Option<&str>,
+) -> Response {
+    let service = &config.service_name;
+
+    let app_config = state.config();
+
+    let env = &state.template_env;
+
+    let status = match systemd_status_html(service, &app_config.redact_regexes) {
+        Ok(status) => Some(status),
+        Err(e) if e.is::<NoSuchUnit>() => {
+            return (StatusCode::NOT_FOUND, e.to_string()).into_response();
+        }
+        Err(e) => {
+            error!("{e}");
+            None
+        }
+    };
+
+    let unit_cat = get_unit_cat(service, &app_config.redact_regexes)
+        .map_err(|e| error!("{e}"))
+        .ok();
+
+    let show_logs = config.show_logs && !app_config.disable_logs;
+
+    // Read by the page when the section is opened
+    let collapse_logs = show_logs && config.logs_collapsed_by_default;
+
+    let (journal, journal_error) = match show_logs && !collapse_logs {
+        true => match journalctl_html(
+            service,
+            filter,
+            &app_config.timezone,
+            &app_config.redact_regexes,
+        ) {
+            Ok(journal) => (Some(journal), None),
+            Err(e) if e.is::<BadPattern>() => (None, Some(e.to_string())),
+            Err(e) => {
+                error!("{e}");
+                (None, None)
+            }
+        },
+        false => (None, None),
+    };
+
+    let boots = match show_logs && !collapse_logs {
+        true => journalctl_boots()
+            .map_err(|e| error!("{e}"))
+            .unwrap_or_default(),
+        false => Vec::new(),
+    };
+
+    let description = unit_description(config);
+
+    let detail = systemctl_for(state, host)
+        .create_unit(service)
+        .map_err(anyhow::Error::from)
+        .and_then(|unit| get_unit_detail(&unit, &app_config))
+        .map_err(|e| error!("Error getting unit detail: {e}"))
+        .ok();
+
+    // The action endpoints act on the managed machine, not on the other hosts
+    let can_act = !app_config.read_only && role >= Role::Operator && host.is_none();
+
+    let actions = match can_act {
+        true => action_buttons(&config.allowed_actions, service, config.socket.as_deref()),
+        false => Vec::new(),
+    };
+
+    let custom_actions = match can_act {
+        true => custom_action_buttons(&app_config, config),
+        false => Vec::new(),
+    };
+
+    let response = env.get_template("commands.html").and_then(|template| {
+        template.render(context! {
+            service,
+            description,
+            links => config.links,
+            role,
+            host => host.map(|h| &h.name),
+            detail,
+            status,
+            unit_cat,
+            journal,
+            journal_error,
+            layout,
+            outcome,
+            custom_actions,
+            show_logs,
+            collapse_logs,
+            log_lines => DETAIL_LOG_LINES,
+            logs_disabled => config.show_logs && app_config.disable_logs,
+            actions,
+            boot => filter.boot,
+            boots,
+            priority => filter.level,
+            since_start => filter.since.is_some(),
+            grep => filter.grep,
+            timestamps => filter.timestamps,
+            levels => [LogLevel::Error, LogLevel::Warning, LogLevel::Info, LogLevel::Debug],
+            maintenance => state.maintenance.load(Ordering::Relaxed),
+            app_name => app_config.app_name,
+            hostname => app_config.instance_hostname(),
+        })
+    });
+
+    match response {
+        Ok(r) => Html(r).into_response(),
+        Err(e) => {
+            error!("Could not render template 'commands': {e:#}");
+            Html(fallback_service(
+                service,
+                status.as_deref(),
+                journal.as_deref(),
+            ))
+            .into_response()
+        }
+    }
+}
+
+/// A button of the detailed view, with the command its endpoint runs so it can be run by hand
+#[derive(Serialize)]
+struct ActionButton {
+    action: Action,
+    signal: Option<&'static str>,
+    /// Acts on the socket of the service
+    socket: bool,
+    command: String,
+}
+
+/// One button per action, and per signal for `kill`. With a `socket`, one more per action but
+/// `kill` for it, as a socket has no processes of its own
+fn action_buttons(actions: &[Action], service: &str, socket: Option<&str>) -> Vec<ActionButton> {
+    let button = |action: Action, signal: Option<&'static str>, unit: &str| {
+        let args: Vec<String> = signal
+            .map(|s| format!("--signal={s}"))
+            .into_iter()
+            .collect();
+        ActionButton {
+            action,
+            signal,
+            socket: unit != service,
+            command: command_line(&action_command(action.verb(), &args, unit)),
+        }
+    };
+
+    let mut buttons: Vec<ActionButton> = actions
+        .iter()
+        .flat_map(|&action| match action {
+            Action::Kill => ALLOWED_SIGNALS
+                .iter()
+                .map(|&signal| button(action, Some(signal), service))
+                .collect(),
+            _ => vec![button(action, None, service)],
+        })
+        .collect();
+
+    if let Some(socket) = socket {
+        buttons.extend(
+            actions
+                .iter()
+                .filter(|&&action| action != Action::Kill)
+                .map(|&action| button(action, None, socket)),
+        );
+    }
+
+    buttons
+}
+
+#[derive(Serialize)]
+struct CustomActionButton<'a> {
+    name: &'a str,
+    label: &'a str,
+    command: String,
+}
+
+/// One button per custom action the service lists, in the order of the `[[custom_action]]`
+/// entries
+fn custom_action_buttons<'a>(
+    app_config: &'a Config,
+    config: &ServiceConfig,
+) -> Vec<CustomActionButton<'a>> {
+    app_config
+        .custom_action
+        .iter()
+        .filter(|action| config.custom_actions.contains(&action.name))
+        .map(|action| CustomActionButton {
+            name: &action.name,
+            label: action.label(),
+            command: command_line(&custom_action_command(
+                &action.args_for(&config.service_name),
+            )),
+        })
+        .collect()
+}
+
+/// Boot time analysis of a service: what it waited for and how long it took to start
+pub async fn handle_analyze(
+    Path(service): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    if !state
+        .config()
+        .service
+        .iter()
+        .any(|s| s.service_name == service)
+    {
+        return (StatusCode::NOT_FOUND, "Service not found").into_response();
+    }
+
+    blocking(move || {
+        let critical_chain = systemd_critical_chain_html(&service)
+            .map_err(|e| error!("{e}"))
+            .ok();
+
+        let blame = systemd_blame_html(&service).map_err(|e| error!("{e}")).ok();
+
+        let template = state
+            .template_env
+            .get_template("analyze.html")
+            .map_err(|e| error!("Could not load template 'analyze': {e}"));
+
+        let Ok(template) = template else {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+        };
+
+        match template.render(context! { service, critical_chain, blame }) {
+            Ok(r) => Html(r).into_response(),
+            Err(e) => {
+                error!("Could not render template 'analyze': {e}");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
+            }
+        }
+    })
+    .await
+}
+
+/// Tree of the units `service` pulls in, with the configured ones linked to their detailed view
+pub async fn handle_dependencies(
+    Path(service): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    let config = state.config();
+
+    if !config.service.iter().any(|s| s.service_name == service) {
+        return (StatusCode::NOT_FOUND, "Service not found").into_response();
+    }
+
+    blocking(move || {
+        let tree = systemctl_list_dependencies(&service)
+            .map_err(|e| error!("{e}"))
+            .ok();
+
+        let configured: Vec<&str> = config
+            .service
+            .iter()
+            .map(|s| s.service_name.as_str())
+            .collect();
+
+        let template = state
+            .template_env
+            .get_template("dependencies.html")
+            .map_err(|e| error!("Could not load template 'dependencies': {e}"));
+
+        let Ok(template) = template else {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+        };
+
+        match template.render(context! { service, tree, configured }) {
+            Ok(r) => Html(r).into_response(),
+            Err(e) => {
+                error!("Could not render template 'dependencies': {e}");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
+            }
+        }
+    })
+    .await
+}
+
+/// Maximum number of entries returned by the logs endpoint
+const MAX_LOG_LINES: u32 = 1000;
+
+#[derive(Deserialize)]
+pub struct LogsQuery {
+    #[serde(default = "default_log_lines")]
+    lines: u32,
+    boot: Option<i32>,
+    priority: Option<LogLevel>,
+    grep: Option<String>,
+    /// `cursor` of the last entry already seen, to only get the newer ones
+    cursor: Option<String>,
+}
+
+fn default_log_lines() -> u32 {
+    100
+}
+
+pub async fn handle_logs(
+    Path(service): Path<String>,
+    CurrentRole(role): CurrentRole,
+    Query(query): Query<LogsQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let app_config = state.config();
+
+    let filter = match logs_filter(&app_config, &service, query, role) {
+        Ok(filter) => filter,
+        Err(refusal) => return refusal.into_response(),
+    };
+
+    blocking(move || {
+        match journalctl_json(
+            &service,
+            &filter,
+            &app_config.timezone,
+            &app_config.redact_regexes,
+        ) {
+            Ok(entries) => Json(entries).into_response(),
+            Err(e) => logs_error(e),
+        }
+    })
+    .await
+}
+
+/// `/logs` as the HTML lines of the detailed view, for its collapsed log section
+pub async fn handle_logs_html(
+    Path(service): Path<String>,
+    CurrentRole(role): CurrentRole,
+    Query(query): Query<LogsQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let app_config = state.config();
+
+    let filter = match logs_filter(&app_config, &service, query, role) {
+        Ok(filter) => filter,
+        Err(refusal) => return refusal.into_response(),
+    };
+
+    blocking(move || {
+        match journalctl_html(
+            &service,
+            &filter,
+            &app_config.timezone,
+            &app_config.redact_regexes,
+        ) {
+            Ok(html) => Html(html).into_response(),
+            Err(e) => logs_error(e),
+        }
+    })
+    .await
+}
+
+/// Entries after `cursor` with the cursor to pass on the next call
+#[derive(Serialize)]
+struct LogsPage {
+    entries: Vec<LogEntry>,
+    /// `cursor` of the last entry, or the one given when there is nothing new
+    cursor: Option<String>,
+}
+
+/// `/logs` for polling: without `cursor` the last `lines` entries, with it only the entries
+/// written since, oldest first. The returned cursor is passed on the next call
+pub async fn handle_logs_since_cursor(
+    Path(service): Path<String>,
+    CurrentRole(role): CurrentRole,
+    Query(query): Query<LogsQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let app_config = state.config();
+
+    let filter = match logs_filter(&app_config, &service, query, role) {
+        Ok(filter) => filter,
+        Err(refusal) => return refusal.into_response(),
+    };
+
+    blocking(move || {
+        match journalctl_json(
+            &service,
+            &filter,
+            &app_config.timezone,
+            &app_config.redact_regexes,
+        ) {
+            Ok(entries) => {
+                let cursor = entries
+                    .last()
+                    .map(|entry| entry.cursor.clone())
+                    .or(filter.after_cursor);
+                Json(LogsPage { entries, cursor }).into_response()
+            }
+            Err(e) => logs_error(e),
+        }
+    })
+    .await
+}
+
+fn logs_error(e: anyhow::Error) -> Response {
+    if e.is::<BadPattern>() || e.is::<BadCursor>() {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+
+    error!("{e}");
+    (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
+}
+
+/// Checks that the logs of `service` can be read and turns the query into a filter
+fn logs_filter(
+    app_config: &Config,
+    service: &str,
+    query: LogsQuery,
+    role: Role,
+) -> Result<JournalFilter, Refusal> {
+    let Some(config) = app_config
+        .service
+        .iter()
+        .find(|a| a.service_name == service)
+    else {
+        return Err(Refusal::new(StatusCode::NOT_FOUND, "Service not found"));
+    };
+
+    if !config.show_logs || app_config.disable_logs {
+        return Err(Refusal::new(
+            StatusCode::FORBIDDEN,
+            "Logs are disabled for this service",
+        ));
+    }
+
+    if query
+        .boot
+        .is_some_and(|boot| !(MAX_BOOT_OFFSET..=0).contains(&boot))
+    {
+        return Err(Refusal::new(
+            StatusCode::BAD_REQUEST,
+            format!("boot must be between {MAX_BOOT_OFFSET} and 0"),
+        ));
+    }
+
+    let grep = check_grep(query.grep, role, app_config)?;
+
+    Ok(JournalFilter {
+        lines: query.lines.min(MAX_LOG_LINES),
+        boot: query.boot,
+        level: query.priority,
+        since: None,
+        grep,
+        after_cursor: query.cursor,
+        timestamps: app_config.log_timestamps,
+        identifier: config.journal_identifier.clone(),
+    })
+}
+
+/// Role of the request, from its bearer token. `admin_token` is an admin. Without a known token
+/// the request is a viewer when users are configured and an operator otherwise, which is how the
+/// dashboard behaved before users existed
+fn request_role(state: &AppState, headers: &HeaderMap) -> Role {
+    let config = state.config();
+
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let Some(token) = token else {
+        return match config.user.is_empty() {
+            true => Role::Operator,
+            false => Role::Viewer,
+        };
+    };
+
+    if config
+        .admin_token
+        .as_deref()
+        .is_some_and(|admin_token| tokens_match(token, admin_token))
+    {
+        return Role::Admin;
+    }
+
+    match config
+        .user
+        .iter()
+        .find(|user| tokens_match(token, &user.token))
+    {
+        Some(user) => user.role,
+        None if config.user.is_empty() => Role::Operator,
+        None => Role::Viewer,
+    }
+}
+
+/// Compares a token in a time that does not depend on where it differs from `expected`, so it
+/// cannot be guessed byte by byte from how long the answers take
+pub fn tokens_match(token: &str, expected: &str) -> bool {
+    token.len() == expected.len()
+        && token
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn is_admin(state: &AppState, headers: &HeaderMap) -> bool {
+    request_role(state, headers) == Role::Admin
+}
+
+/// Extracts the role of the request
+pub struct CurrentRole(pub Role);
+
+impl FromRequestParts<AppState> for CurrentRole {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(CurrentRole(request_role(state, &parts.headers)))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceQuery {
+    enabled: Option<bool>,
+}
+
+/// Sets maintenance mode to `?enabled=` or toggles it when omitted
+pub async fn handle_maintenance(
+    headers: HeaderMap,
+    Query(query): Query<MaintenanceQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    if !is_admin(&state, &headers) {
+        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+
+    let enabled = match query.enabled {
+        Some(enabled) => {
+            state.maintenance.store(enabled, Ordering::Relaxed);
+            enabled
+        }
+        None => !state.maintenance.fetch_xor(true, Ordering::Relaxed),
+    };
+
+    info!(
+        "Maintenance mode {}",
+        if enabled { "enabled" } else { "disabled" }
+    );
+
+    (StatusCode::OK, format!("maintenance: {enabled}")).into_response()
+}
+
+/// Re-reads the configuration file and swaps it in when it is valid. The running configuration
+/// is kept on error. Returns the services that were added, removed or changed
+pub async fn handle_reload(headers: HeaderMap, State(state): State<AppState>) -> Response {
+    if !is_admin(&state, &headers) {
+        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+
+    blocking(move || reload(&state)).await
+}
+
+fn reload(state: &AppState) -> Response {
+    if let ConfigSource::Stdin = *state.config_source {
+        return (
+            StatusCode::CONFLICT,
+            "The configuration was read from stdin and cannot be reloaded",
+        )
+            .into_response();
+    }
+
+    match reload_config(state) {
+        Ok(diff) => Json(diff).into_response(),
+        Err(e) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Configuration error: {e:#}"),
+        )
+            .into_response(),
+    }
+}
+
+/// Loads the configuration again and swaps it in if it is valid, keeping the running one
+/// otherwise
+pub fn reload_config(state: &AppState) -> anyhow::Result<ConfigDiff> {
+    let config = load_and_validate(&state.config_source)
+        .and_then(|config| same_remote_host(&state.config(), config))
+        .and_then(|config| check_units(&build_systemctl(&config, None), &config).map(|_| config))
+        .inspect_err(|e| error!("Configuration reload failed: {e:#}"))?;
+
+    set_subprocess_limit(
+        config.max_subprocesses,
+        Duration::from_secs(config.subprocess_timeout_secs),
+        config.subprocess_retries,
+    );
+
+    let diff = {
+        let mut current = state.config.write().unwrap();
+        let diff = diff_services(&current, &config);
+        *current = Arc::new(config);
+        diff
+    };
+
+    info!(
+        "Configuration reloaded: {} added, {} removed, {} changed",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len()
+    );
+
+    for name in &diff.added {
+        info!("Added {name}");
+    }
+
+    for name in &diff.removed {
+        info!("Removed {name}");
+    }
+
+    Ok(diff)
+}
+
+/// Every call keeps going to the remote host the dashboard was started with, so a reload that
+/// changes it is refused rather than half applied
+fn same_remote_host(running: &Config, config: Config) -> anyhow::Result<Config> {
+    match config.remote_host() == running.remote_host() {
+        true => Ok(config),
+        false => Err(anyhow::anyhow!(
+            "`host` changed from {:?} to {:?}, which only takes effect on restart",
+            running.remote_host(),
+            config.remote_host()
+        )),
+    }
+}
+
+/// The running configuration, after the patterns are expanded and the environment overrides
+/// applied, with the tokens hidden
+pub async fn handle_config(headers: HeaderMap, State(state): State<AppState>) -> Response {
+    if !is_admin(&state, &headers) {
+        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+
+    match state.config().redacted() {
+        Ok(config) => Json(config).into_response(),
+        Err(e) => {
+            error!("{e:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AllUnitsQuery {
+    /// Only units whose name contains this, ignoring case
+    filter: Option<String>,
+}
+
+/// Every service unit of the machine, configured or not, to pick new services from
+pub async fn handle_all_units(
+    headers: HeaderMap,
+    Query(query): Query<AllUnitsQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    if !is_admin(&state, &headers) {
+        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+
+    let filter = query.filter.unwrap_or_default().to_lowercase();
+
+    blocking(move || match systemctl_list_all_services() {
+        Ok(units) => Json(
+            units
+                .into_iter()
+                .filter(|u| u.unit.to_lowercase().contains(&filter))
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(e) => {
+            error!("{e:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
+        }
+    })
+    .await
+}
+
+/// Adds a service to the configuration file and reloads it. Only for a configuration read from a
+/// file, which is rewritten keeping its comments
+pub async fn handle_add_service(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Json(service): Json<ServiceConfig>,
+) -> Response {
+    if !is_admin(&state, &headers) {
+        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+
+    let config = state.config();
+
+    if config.read_only {
+        return (StatusCode::FORBIDDEN, "The dashboard is read-only").into_response();
+    }
+
+    let ConfigSource::File(path) = &*state.config_source else {
+        return (
+            StatusCode::CONFLICT,
+            "The configuration is not read from a file and cannot be edited",
+        )
+            .into_response();
+    };
+
+    if config
+        .service
+        .iter()
+        .any(|s| s.service_name == service.service_name)
+    {
+        return (StatusCode::CONFLICT, "The service is already configured").into_response();
+    }
+
+    let path = path.clone();
+
+    blocking(move || {
+        let unit = match systemctl_for(&state, None).create_unit(&service.service_name) {
+            Ok(unit) => unit,
+            Err(e) => {
+                error!("Failed to create unit for {}: {e}", service.service_name);
+                return (StatusCode::UNPROCESSABLE_ENTITY, "Unit not found").into_response();
+            }
+        };
+
+        if let systemctl::State::Masked = unit.state {
+            let message = match systemd_show_parse::<String>("LoadState", &unit.name) {
+                Ok(load_state) if load_state == "masked" => "Unit is masked",
+                _ => "Unit not found",
+            };
+            return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response();
+        }
+
+        if let Err(e) = append_service(&path, &service) {
+            error!("Could not add {}: {e:#}", service.service_name);
+            return (StatusCode::UNPROCESSABLE_ENTITY, format!("{e:#}")).into_response();
+        }
+
+        info!("Added {} to {}", service.service_name, path.display());
+
+        match reload_config(&state) {
+            Ok(diff) => (StatusCode::CREATED, Json(diff)).into_response(),
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("The service was added but the configuration could not be reloaded: {e:#}"),
+            )
+                .into_response(),
+        }
+    })
+    .await
+}
+
+/// Removes a service from the configuration file and reloads it. The unit itself is left alone
+pub async fn handle_remove_service(
+    headers: HeaderMap,
+    Path(service): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    if !is_admin(&state, &headers) {
+        return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+
+    let config = state.config();
+
+    if config.read_only {
+        return (StatusCode::FORBIDDEN, "The dashboard is read-only").into_response();
+    }
+
+    let ConfigSource::File(path) = &*state.config_source else {
+        return (
+            StatusCode::CONFLICT,
+            "The configuration is not read from a file and cannot be edited",
+        )
+            .into_response();
+    };
+
+    if !config.service.iter().any(|s| s.service_name == service) {
+        return (StatusCode::NOT_FOUND, "Service not found").into_response();
+    }
+
+    let path = path.clone();
+
+    blocking(move || {
+        if let Err(e) = remove_service(&path, &service) {
+            error!("Could not remove {service}: {e:#}");
+            return (StatusCode::UNPROCESSABLE_ENTITY, format!("{e:#}")).into_response();
+        }
+
+        info!("Removed {service} from {}", path.display());
+
+        match reload_config(&state) {
+            Ok(_) => Json(state.config().service.clone()).into_response(),
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!(
+                    "The service was removed but the configuration could not be reloaded: {e:#}"
+                ),
+            )
+                .into_response(),
+        }
+    })
+    .await
+}
+
+#[derive(Serialize)]
+struct TagCount<'a> {
+    tag: &'a str,
+    count: usize,
+}
+
+/// Every tag of the configured services, by name, with the number of services that have it
+pub async fn handle_tags(State(state): State<AppState>) -> Response {
+    let config = state.config();
+
+    let mut counts: Vec<TagCount> = Vec::new();
+
+    for tag in config.service.iter().flat_map(|s| &s.tags) {
+        match counts.iter_mut().find(|c| c.tag == tag) {
+            Some(count) => count.count += 1,
+            None => counts.push(TagCount { tag, count: 1 }),
+        }
+    }
+
+    counts.sort_by(|a, b| a.tag.cmp(b.tag));
+
+    Json(counts).into_response()
+}
+
+/// Used when no `favicon` is configured
+const DEFAULT_FAVICON: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 100 100"><text y=".9em" font-size="90">⚙️</text></svg>"#;
+
+pub async fn handle_favicon(State(state): State<AppState>) -> Response {
+    let config = state.config();
+
+    let Some(path) = &config.favicon else {
+        return ([(header::CONTENT_TYPE, "image/svg+xml")], DEFAULT_FAVICON).into_response();
+    };
+
+    match tokio::fs::read(path).await {
+        Ok(icon) => (
+            [(header::CONTENT_TYPE, favicon_type(path).unwrap_or_default())],
+            icon,
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Could not read favicon '{}': {e}", path.display());
+            (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
+        }
+    }
+}
+
+/// Web app manifest, which makes the dashboard installable on phones
+pub async fn handle_manifest(State(state): State<AppState>) -> Response {
+    let config = state.config();
+
+    let icon_type = match &config.favicon {
+        Some(path) => favicon_type(path).unwrap_or_default(),
+        None => "image/svg+xml",
+    };
+
+    let manifest = serde_json::json!({
+        "name": config.app_name,
+        "short_name": config.app_name,
+        "start_url": "/",
+        "display": "standalone",
+        "background_color": "#1e1e2e",
+        "theme_color": "#1e1e2e",
+        "icons": [{ "src": "/api/favicon", "sizes": "any", "type": icon_type }],
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/manifest+json")],
+        manifest.to_string(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(config: &str) -> AppState {
+        AppState::new(
+            Arc::new(toml::from_str(config).unwrap()),
+            ConfigSource::Env(String::new()),
+            crate::templates::builtin_templates(),
+        )
+    }
+
+    const DEMO_SERVICE: &str = r#"
+        [[service]]
+        service_name = "demo.service"
+        friendly_name = "Demo"
+    "#;
+
+    #[test]
+    fn service_fields_are_the_serialized_ones() {
+        let config: Config = toml::from_str(DEMO_SERVICE).unwrap();
+        let service = ServiceInfo::unavailable(config.service[0].clone());
+
+        let serde_json::Value::Object(serialized) = serde_json::to_value(&service).unwrap() else {
+            panic!("ServiceInfo is not serialized as an object");
+        };
+
+        let mut expected: Vec<&str> = serialized.keys().map(String::as_str).collect();
+        expected.extend(["service_name", "friendly_name"]);
+        expected.sort_unstable();
+
+        let mut fields = SERVICE_FIELDS.to_vec();
+        fields.sort_unstable();
+
+        assert_eq!(fields, expected);
+        assert!(LISTED_FIELDS.iter().all(|field| fields.contains(field)));
+    }
+
+    #[test]
+    fn grep_is_kept_from_non_admins_while_logs_are_redacted() {
+        let grep = |pattern: &str, role: Role, config: &Config| {
+            check_grep(Some(pattern.to_owned()), role, config).map_err(|refusal| refusal.0)
+        };
+
+        let mut config: Config = toml::from_str("").unwrap();
+        assert_eq!(
+            grep("timeout", Role::Viewer, &config),
+            Ok(Some("timeout".to_owned()))
+        );
+        assert_eq!(grep("", Role::Viewer, &config), Ok(None));
+        assert_eq!(
+            grep("a\nb", Role::Admin, &config),
+            Err(StatusCode::BAD_REQUEST)
+        );
+
+        config.redact_regexes = vec![regex::Regex::new(r"token=\S+").unwrap()];
+        assert_eq!(
+            grep("token=a", Role::Operator, &config),
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            grep("token=a", Role::Admin, &config),
+            Ok(Some("token=a".to_owned()))
+        );
+        assert_eq!(grep("", Role::Viewer, &config), Ok(None));
+    }
+
+    #[test]
+    fn only_operators_can_act_outside_maintenance() {
+        let demo = state(DEMO_SERVICE);
+        let refused = |state: &AppState, service: &str, role: Role| {
+            check_can_act(state, service, role).unwrap_err().0
+        };
+
+        assert!(check_can_act(&demo, "demo.service", Role::Operator).is_ok());
+        assert!(check_can_act(&demo, "demo.service", Role::Admin).is_ok());
+        assert_eq!(
+            refused(&demo, "other.service", Role::Admin),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            refused(&demo, "demo.service", Role::Viewer),
+            StatusCode::FORBIDDEN
+        );
+
+        let read_only = state(&format!("read_only = true\n{DEMO_SERVICE}"));
+        assert_eq!(
+            refused(&read_only, "demo.service", Role::Admin),
+            StatusCode::FORBIDDEN
+        );
+
+        demo.maintenance.store(true, Ordering::Relaxed);
+        assert_eq!(
+            refused(&demo, "demo.service", Role::Admin),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[tokio::test]
+    async fn no_cache_keeps_an_explicit_cache_control() {
+        let cards = no_cache("cards".into_response()).await;
+        assert_eq!(
+            cards.headers()[header::CACHE_CONTROL],
+            "no-cache, must-revalidate"
+        );
+
+        let icon =
+            no_cache(([(header::CACHE_CONTROL, "max-age=86400")], "icon").into_response()).await;
+        assert_eq!(icon.headers()[header::CACHE_CONTROL], "max-age=86400");
+    }
+
+    #[test]
+    fn health_only_counts_enabled_services_as_down() {
+        let states = |states: &[(&str, bool)]| -> Vec<(String, bool)> {
+            states
+                .iter()
+                .map(|&(active, enabled)| (active.to_owned(), enabled))
+                .collect()
+        };
+
+        // (what, states, status)
+        let cases = [
+            ("no service", states(&[]), HealthStatus::Ok),
+            (
+                "every enabled service active",
+                states(&[("active", true), ("inactive", false)]),
+                HealthStatus::Ok,
+            ),
+            (
+                "one enabled service failed",
+                states(&[("active", true), ("failed", true)]),
+                HealthStatus::Degraded,
+            ),
+            (
+                "every enabled service down",
+                states(&[("failed", true), ("inactive", true), ("active", false)]),
+                HealthStatus::Down,
+            ),
+        ];
+
+        for (what, states, status) in cases {
+            assert_eq!(assess_health(&states).0, status, "{what}");
+        }
+
+        let (_, counts) = assess_health(&states(&[
+            ("active", true),
+            ("failed", true),
+            ("inactive", false),
+        ]));
+        assert_eq!((counts.total, counts.running, counts.failed), (3, 1, 1));
+    }
+
+    #[test]
+    fn copied_commands_are_the_ones_the_server_runs() {
+        let buttons = action_buttons(
+            &[Action::Restart, Action::Stop, Action::Mask],
+            "demo.service",
+            None,
+        );
+        let commands: Vec<&str> = buttons.iter().map(|b| b.command.as_str()).collect();
+
+        assert_eq!(
+            commands,
+            [
+                "systemctl restart --no-block demo.service",
+                "systemctl stop --no-block demo.service",
+                "systemctl mask demo.service",
+            ]
+        );
+    }
+
+    #[test]
+    fn tokens_match_only_the_same_token() {
+        assert!(tokens_match("admin-s3cret", "admin-s3cret"));
+        assert!(!tokens_match("admin-s3creT", "admin-s3cret"));
+        assert!(!tokens_match("admin-s3cre", "admin-s3cret"));
+        assert!(!tokens_match("", "admin-s3cret"));
     }
 }
@@ -0,0 +1,280 @@
+use std::convert::Infallible;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use log::error;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+
+use crate::AppState;
+use crate::config::Config;
+use crate::helper::{LogEntry, SUBPROCESSES_SPAWNED, for_host, journal_matches, log_entry_html};
+
+/// Lines are sent in batches, at most every `FLUSH_INTERVAL` or as soon as `MAX_BATCH` are waiting
+const FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+const MAX_BATCH: usize = 200;
+
+/// Batches queued for a slow client. Once full, new lines are dropped and counted
+const BUFFER: usize = 16;
+
+/// Lines of history sent when a client connects
+const INITIAL_LINES: u32 = 10;
+
+/// Journal followers running, bounded by `max_log_streams`
+#[derive(Default)]
+pub struct LogStreams(AtomicUsize);
+
+/// Place of a follower among the `LogStreams`, given back when it is dropped
+struct LogStreamSlot(Arc<LogStreams>);
+
+impl LogStreams {
+    fn try_acquire(self: &Arc<Self>, max: usize) -> Option<LogStreamSlot> {
+        self.0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |running| {
+                (running < max).then_some(running + 1)
+            })
+            .ok()?;
+        Some(LogStreamSlot(self.clone()))
+    }
+}
+
+impl Drop for LogStreamSlot {
+    fn drop(&mut self) {
+        self.0.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Follows the journal of a service as Server-Sent Events. Each `logs` event holds the HTML of a
+/// batch of lines, preceded by a marker when lines were dropped because the client fell behind
+pub async fn handle_logs_stream(
+    Path(service): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    let app_config = state.config();
+
+    let Some(config) = app_config
+        .service
+        .iter()
+        .find(|a| a.service_name == service)
+    else {
+        return (StatusCode::NOT_FOUND, "Service not found").into_response();
+    };
+
+    if !config.show_logs || app_config.disable_logs {
+        return (StatusCode::FORBIDDEN, "Logs are disabled for this service").into_response();
+    }
+
+    stream_journal(&[service], &state, app_config, false)
+}
+
+/// Follows the journals of several services merged in time order, e.g.
+/// `?service=api.service&service=worker.service`. Each line is labelled with its unit
+pub async fn handle_merged_logs_stream(
+    Query(pairs): Query<Vec<(String, String)>>,
+    State(state): State<AppState>,
+) -> Response {
+    let app_config = state.config();
+
+    let services: Vec<String> = pairs
+        .into_iter()
+        .filter(|(key, _)| key == "service")
+        .map(|(_, value)| value)
+        .collect();
+
+    if services.is_empty() {
+        return (StatusCode::BAD_REQUEST, "At least one service is needed").into_response();
+    }
+
+    for service in &services {
+        let Some(config) = app_config
+            .service
+            .iter()
+            .find(|a| &a.service_name == service)
+        else {
+            return (
+                StatusCode::NOT_FOUND,
+                format!("Service {service} not found"),
+            )
+                .into_response();
+        };
+
+        if !config.show_logs || app_config.disable_logs {
+            return (
+                StatusCode::FORBIDDEN,
+                format!("Logs are disabled for {service}"),
+            )
+                .into_response();
+        }
+    }
+
+    stream_journal(&services, &state, app_config, true)
+}
+
+/// Runs `journalctl --follow` on `units` and streams its lines, with the unit of each line when
+/// `label` is set. Answers 503 once `max_log_streams` are already running
+fn stream_journal(
+    units: &[String],
+    state: &AppState,
+    config: Arc<Config>,
+    label: bool,
+) -> Response {
+    // Not counted against `max_subprocesses`, a follower would hold its slot for as long as
+    // the client stays connected
+    let Some(slot) = state.log_streams.try_acquire(config.max_log_streams) else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too many log streams are open, try again later",
+        )
+            .into_response();
+    };
+
+    let mut command = std::process::Command::new("journalctl");
+
+    let identifiers: Vec<(&str, Option<&str>)> = units
+        .iter()
+        .map(|unit| {
+            let identifier = config
+                .service
+                .iter()
+                .find(|s| &s.service_name == unit)
+                .and_then(|s| s.journal_identifier.as_deref());
+            (unit.as_str(), identifier)
+        })
+        .collect();
+
+    command.args(journal_matches(&identifiers));
+
+    command
+        .arg("--follow")
+        .arg(format!("--lines={INITIAL_LINES}"))
+        .arg("--output=json");
+
+    let child = Command::from(for_host(&command).unwrap_or(command))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn();
+
+    SUBPROCESSES_SPAWNED.fetch_add(1, Ordering::Relaxed);
+
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Could not follow the journal of {}: {e}", units.join(", "));
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+        }
+    };
+
+    let (tx, rx) = mpsc::channel(BUFFER);
+
+    tokio::spawn(forward_logs(child, tx, config, label, slot));
+
+    Sse::new(ReceiverStream::new(rx).map(Ok::<Event, Infallible>))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Reads the journal until the client disconnects. Dropping `child` kills journalctl and dropping
+/// `_slot` lets another stream start
+async fn forward_logs(
+    mut child: Child,
+    tx: mpsc::Sender<Event>,
+    config: Arc<Config>,
+    label: bool,
+    _slot: LogStreamSlot,
+) {
+    let Some(stdout) = child.stdout.take() else {
+        return;
+    };
+
+    let mut lines = BufReader::new(stdout).lines();
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+    let mut batch: Vec<String> = Vec::new();
+    let mut dropped: usize = 0;
+
+    loop {
+        let flush = tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => {
+                    let entry =
+                        LogEntry::from_json(&line, &config.timezone, &config.redact_regexes);
+                    match entry.and_then(|e| {
+                        Ok((log_entry_html(&e, config.log_timestamps, &config.timezone)?, e.unit))
+                    }) {
+                        Ok((html, Some(unit))) if label => batch.push(format!(
+                            "<span class=\"log-unit\">{}</span> {html}",
+                            htmlescape::encode_minimal(&unit)
+                        )),
+                        Ok((html, _)) => batch.push(html),
+                        Err(e) => error!("{e}"),
+                    }
+                    batch.len() >= MAX_BATCH
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    error!("Could not read the journal: {e}");
+                    break;
+                }
+            },
+            _ = interval.tick() => {
+                if tx.is_closed() {
+                    break;
+                }
+                !batch.is_empty()
+            }
+        };
+
+        if !flush {
+            continue;
+        }
+
+        let mut data = String::new();
+        if dropped > 0 {
+            data.push_str(&format!(
+                "<span class=\"log-dropped\">… {dropped} lines dropped …</span>\n"
+            ));
+        }
+        data.push_str(&batch.concat());
+
+        // SSE fields cannot contain carriage returns
+        let event = Event::default().event("logs").data(data.replace('\r', ""));
+
+        match tx.try_send(event) {
+            Ok(()) => dropped = 0,
+            Err(TrySendError::Full(_)) => dropped += batch.len(),
+            Err(TrySendError::Closed(_)) => break,
+        }
+
+        batch.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_streams_are_bounded() {
+        let streams = Arc::new(LogStreams::default());
+
+        let first = streams.try_acquire(2).unwrap();
+        let _second = streams.try_acquire(2).unwrap();
+        assert!(streams.try_acquire(2).is_none());
+
+        // A closed stream makes room for the next one
+        drop(first);
+        assert!(streams.try_acquire(2).is_some());
+    }
+}
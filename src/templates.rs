@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use log::warn;
+use minijinja::Environment;
+
+use crate::ServiceInfo;
+
+const TEMPLATE_DIR: &str = "./templates";
+
+/// Copies of `templates/` compiled into the binary
+const BUILTIN_TEMPLATES: [(&str, &str); 6] = [
+    ("cards.html", include_str!("../templates/cards.html")),
+    ("commands.html", include_str!("../templates/commands.html")),
+    ("analyze.html", include_str!("../templates/analyze.html")),
+    ("welcome.html", include_str!("../templates/welcome.html")),
+    (
+        "dependencies.html",
+        include_str!("../templates/dependencies.html"),
+    ),
+    ("wall.html", include_str!("../templates/wall.html")),
+];
+
+/// Loads the templates from `./templates`, so they can be edited without rebuilding, or uses
+/// the built-in ones when the directory is missing
+pub fn template_env() -> Environment<'static> {
+    if !Path::new(TEMPLATE_DIR).is_dir() {
+        warn!("{TEMPLATE_DIR} not found. Will use the built-in templates");
+        return builtin_templates();
+    }
+
+    let mut env = Environment::new();
+    env.set_loader(minijinja::path_loader(TEMPLATE_DIR));
+    env
+}
+
+/// The templates compiled into the binary, which do not depend on the working directory
+pub fn builtin_templates() -> Environment<'static> {
+    let mut env = Environment::new();
+
+    for (name, source) in BUILTIN_TEMPLATES {
+        env.add_template(name, source)
+            .expect("built-in templates are valid");
+    }
+
+    env
+}
+
+const FALLBACK_BANNER: &str =
+    "<p class=\"maintenance-banner\">Template error, showing fallback</p>\n";
+
+/// Same words as the `state` macro of `cards.html`
+fn state_label(service: &ServiceInfo) -> &'static str {
+    match service {
+        s if s.unavailable => "unavailable",
+        s if s.not_found => "not found",
+        s if s.running => "running",
+        s if s.exited => "exited",
+        s if s.active => "active",
+        s if s.failed => "failed",
+        _ => "stopped",
+    }
+}
+
+/// Bare list of `services` shown when `cards.html` cannot be loaded or rendered, so a typo in a
+/// template does not leave the dashboard empty
+pub fn fallback_cards<'a>(services: impl Iterator<Item = &'a ServiceInfo>) -> String {
+    let mut html = String::from(FALLBACK_BANNER);
+    html.push_str("<ul class=\"services-fallback\">\n");
+
+    for service in services {
+        let host = service
+            .host
+            .as_deref()
+            .map(|host| format!(" on {}", htmlescape::encode_minimal(host)))
+            .unwrap_or_default();
+
+        html.push_str(&format!(
+            "<li>{}{host}: {}</li>\n",
+            htmlescape::encode_minimal(&service.config.friendly_name),
+            state_label(service)
+        ));
+    }
+
+    html.push_str("</ul>\n");
+    html
+}
+
+/// The already converted status and logs of `service`, for when `commands.html` fails
+pub fn fallback_service(service: &str, status: Option<&str>, journal: Option<&str>) -> String {
+    let mut html = String::from(FALLBACK_BANNER);
+    html.push_str(&format!(
+        "<h2>{}</h2>\n",
+        htmlescape::encode_minimal(service)
+    ));
+
+    for output in [status, journal].into_iter().flatten() {
+        html.push_str(&format!("<pre class=\"command-output\">{output}</pre>\n"));
+    }
+
+    html
+}
+
+/// Where to add services, for when `welcome.html` fails
+pub fn fallback_welcome(config_source: &str) -> String {
+    format!(
+        "{FALLBACK_BANNER}<p>No services configured. Add [[service]] entries to {}</p>\n",
+        htmlescape::encode_minimal(config_source)
+    )
+}
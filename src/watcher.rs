@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::{error, info};
+use notify::{Event, RecursiveMode, Watcher};
+
+use crate::AppState;
+use crate::routes::reload_config;
+
+/// Writes closer together than this are reloaded once, so an editor saving in several steps
+/// does not load a half written file
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Reloads the configuration whenever `path` changes. Runs until the process exits
+pub fn watch_config(state: AppState, path: PathBuf) {
+    if let Err(e) = watch(&state, &path) {
+        error!("Not watching {}: {e:#}", path.display());
+    }
+}
+
+fn watch(state: &AppState, path: &Path) -> Result<()> {
+    let (tx, rx) = mpsc::channel::<notify::Result<Event>>();
+
+    let mut watcher = notify::recommended_watcher(tx).context("Unable to create the watcher")?;
+
+    // Editors often replace the file instead of writing to it, which only the directory sees
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("Unable to watch {}", dir.display()))?;
+
+    info!("Watching {} for changes", path.display());
+
+    let changed = |event: &notify::Result<Event>| match event {
+        Ok(event) => {
+            !event.kind.is_access()
+                && event
+                    .paths
+                    .iter()
+                    .any(|p| p.file_name() == path.file_name())
+        }
+        Err(e) => {
+            error!("Watch error: {e}");
+            false
+        }
+    };
+
+    loop {
+        let event = rx.recv().context("The watcher stopped")?;
+
+        if !changed(&event) {
+            continue;
+        }
+
+        // Wait until the writes settle
+        loop {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+
+        info!("{} changed, reloading", path.display());
+
+        // Errors are logged by `reload_config`, the running configuration is kept
+        let _ = reload_config(state);
+    }
+}
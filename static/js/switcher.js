@@ -0,0 +1,114 @@
+// Quick switcher: Ctrl+K or / opens a search over the service names, Enter opens the
+// detailed view of the selected one
+(function () {
+  let services = null;
+  let matches = [];
+  let selected = 0;
+
+  const overlay = document.createElement("div");
+  overlay.className = "switcher";
+  overlay.hidden = true;
+  overlay.innerHTML =
+    '<div class="switcher-box bg2">' +
+    '<input class="switcher-input" type="text" placeholder="Go to service..." autocomplete="off">' +
+    '<ul class="switcher-results"></ul>' +
+    "</div>";
+
+  const input = overlay.querySelector(".switcher-input");
+  const results = overlay.querySelector(".switcher-results");
+
+  // Every character of the query in order, not necessarily next to each other
+  function score(query, text) {
+    let position = 0;
+    let gaps = 0;
+    for (const c of query) {
+      const found = text.indexOf(c, position);
+      if (found === -1) return null;
+      gaps += found - position;
+      position = found + 1;
+    }
+    return gaps;
+  }
+
+  function search() {
+    const query = input.value.toLowerCase().replace(/\s+/g, "");
+    matches = services
+      .map((service) => {
+        const scores = [service.slug, service.service_name.toLowerCase()]
+          .map((text) => score(query, text))
+          .filter((s) => s !== null);
+        return { service, score: scores.length ? Math.min(...scores) : null };
+      })
+      .filter((match) => match.score !== null)
+      .sort((a, b) => a.score - b.score)
+      .map((match) => match.service);
+    selected = 0;
+    render();
+  }
+
+  function render() {
+    results.replaceChildren(
+      ...matches.map((service, i) => {
+        const item = document.createElement("li");
+        item.className = "switcher-result" + (i === selected ? " switcher-selected" : "");
+        item.textContent = service.friendly_name + " (" + service.service_name + ")";
+        item.addEventListener("click", () => go(service));
+        return item;
+      })
+    );
+  }
+
+  function go(service) {
+    close();
+    htmx.ajax("GET", "/api/service/" + encodeURIComponent(service.service_name), "#detailed-view");
+    document.getElementById("detailed-view").scrollIntoView();
+  }
+
+  async function open() {
+    if (services === null) {
+      const response = await fetch("/api/services/names");
+      services = response.ok ? await response.json() : [];
+    }
+    overlay.hidden = false;
+    input.value = "";
+    search();
+    input.focus();
+  }
+
+  function close() {
+    overlay.hidden = true;
+  }
+
+  input.addEventListener("input", search);
+
+  input.addEventListener("keydown", (event) => {
+    if (event.key === "ArrowDown") {
+      selected = Math.min(selected + 1, matches.length - 1);
+      render();
+    } else if (event.key === "ArrowUp") {
+      selected = Math.max(selected - 1, 0);
+      render();
+    } else if (event.key === "Enter" && matches[selected]) {
+      go(matches[selected]);
+    } else if (event.key === "Escape") {
+      close();
+    } else {
+      return;
+    }
+    event.preventDefault();
+  });
+
+  overlay.addEventListener("click", (event) => {
+    if (event.target === overlay) close();
+  });
+
+  document.addEventListener("keydown", (event) => {
+    const typing = ["INPUT", "SELECT", "TEXTAREA"].includes(document.activeElement.tagName);
+    if ((event.key === "k" && (event.ctrlKey || event.metaKey)) || (event.key === "/" && !typing)) {
+      event.preventDefault();
+      open();
+    }
+  });
+
+  document.addEventListener("DOMContentLoaded", () => document.body.appendChild(overlay));
+})();